@@ -0,0 +1,518 @@
+//! Headless front end: terminal play and tools that need no display.
+
+use std::io::{self, BufRead, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use dark_queen_zer0::clock::{self, Clock, TimeControl};
+use dark_queen_zer0::comun::{BandPlayer, GamePlayer, GameStatus, Turn};
+use dark_queen_zer0::game_manager::{GameManager, MoveError};
+use dark_queen_zer0::ia::arena::{self, ArenaConfig};
+use dark_queen_zer0::ia::brain::Brain;
+use dark_queen_zer0::ia::mcts::Mcts;
+use dark_queen_zer0::ia::network::{Network, UniformNetwork};
+use dark_queen_zer0::ia::puct::{Puct, PuctConfig};
+use dark_queen_zer0::ia::resnet::{ResNet, ResNetConfig};
+use dark_queen_zer0::ia::selfplay::{self, SelfPlayConfig};
+use dark_queen_zer0::ia::train::{self, TrainConfig};
+use dark_queen_zer0::notation::MoveText;
+use dark_queen_zer0::pdn::{self, PdnHeaders};
+use dark_queen_zer0::protocol::dxp::{self, DxpConfig, DxpConnection};
+use dark_queen_zer0::protocol::hub::HubEngine;
+use dark_queen_zer0::protocol::text::{self, TextEngine};
+use dark_queen_zer0::rules::Variant;
+
+const USAGE: &str = "usage:
+  dqz play [--variant <name>] [--fen <fen>] [--red human|computer] [--black human|computer] [--iterations <n>] [--network <file>] [--clock <control>]
+  dqz engine [--variant <name>] [--iterations <n>] [--network <file>]
+  dqz hub [--variant <name>] [--iterations <n>] [--network <file>]
+  dqz dxp listen|connect <host:port> [--variant <name>] [--color red|black] [--minutes <n>] [--moves <n>] [--iterations <n>]
+  dqz selfplay <file> [--variant <name>] [--games <n>] [--threads <n>] [--seed <n>] [--iterations <n>] [--network <file>]
+  dqz train <checkpoint> <games>... [--network <file>] [--epochs <n>] [--batch <n>] [--rate <x>] [--seed <n>] [--losses <file>]
+  dqz arena <candidate> <champion> [--variant <name>] [--games <n>] [--threads <n>] [--seed <n>] [--iterations <n>] [--openings <plies>] [--threshold <score>] [--log <file>] [--promote <file>]
+  dqz perft <depth> [--variant <name>] [--fen <fen>]
+  dqz pdn <file>";
+
+const HELP: &str = "enter a move as 11-15, 22x6 or 22x13x6, or one of:
+  moves  list the legal moves
+  undo   take back your last move; the clocks keep the time already used
+  fen    print the position
+  pdn    print the game so far
+  quit   leave the game
+a clock is given in seconds as 300, 300+5 (increment), 300d5 (delay) or 40/5400 (moves per period)";
+
+/// Options shared by the subcommands, read from `--name value` pairs.
+struct Options {
+    variant: Variant,
+    fen: Option<String>,
+    red: GamePlayer,
+    black: GamePlayer,
+    iterations: Option<u32>,
+    /// Checkpoint of the network guiding the search, instead of random playouts.
+    network: Option<String>,
+    clock: Option<TimeControl>,
+    /// Color played when offering a DXP game.
+    color: Turn,
+    /// DXP time control: `minutes` for every `moves` moves.
+    minutes: u32,
+    moves: u32,
+    /// Self-play games, the threads playing them and the seed of the first.
+    games: usize,
+    threads: Option<usize>,
+    seed: u64,
+    /// Training settings and the file the losses of every epoch are added to.
+    train: TrainConfig,
+    losses: Option<String>,
+    /// Arena settings, the file its results are added to and where a winning candidate goes.
+    arena: ArenaConfig,
+    log: Option<String>,
+    promote: Option<String>,
+    positional: Vec<String>,
+}
+
+fn parse_player(value: &str) -> Result<GamePlayer, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "human" => Ok(GamePlayer::Human),
+        "computer" => Ok(GamePlayer::Computer),
+        _ => Err(format!("unknown player '{}'", value)),
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        variant: Variant::default(),
+        fen: None,
+        red: GamePlayer::Human,
+        black: GamePlayer::Computer,
+        iterations: None,
+        network: None,
+        clock: None,
+        color: Turn::Red,
+        minutes: 10,
+        moves: 75,
+        games: 100,
+        threads: None,
+        seed: 0,
+        train: TrainConfig::default(),
+        losses: None,
+        arena: ArenaConfig::new(Variant::default(), 100),
+        log: None,
+        promote: None,
+        positional: Vec::new(),
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            options.positional.push(arg.clone());
+            continue;
+        }
+
+        let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--variant" => options.variant = value.parse()?,
+            "--fen" => options.fen = Some(value.clone()),
+            "--red" => options.red = parse_player(value)?,
+            "--black" => options.black = parse_player(value)?,
+            "--color" => options.color = match value.to_ascii_lowercase().as_str() {
+                "red" | "white" => Turn::Red,
+                "black" => Turn::Black,
+                _ => return Err(format!("unknown color '{}'", value)),
+            },
+            "--minutes" => options.minutes = value.parse().map_err(|_| format!("bad minutes '{}'", value))?,
+            "--moves" => options.moves = value.parse().map_err(|_| format!("bad moves '{}'", value))?,
+            "--games" => options.games = value.parse().map_err(|_| format!("bad games '{}'", value))?,
+            "--threads" => options.threads = Some(value.parse().map_err(|_| format!("bad threads '{}'", value))?),
+            "--seed" => {
+                options.seed = value.parse().map_err(|_| format!("bad seed '{}'", value))?;
+                options.train.seed = options.seed;
+            }
+            "--epochs" => options.train.epochs = value.parse().map_err(|_| format!("bad epochs '{}'", value))?,
+            "--batch" => options.train.batch_size = value.parse().map_err(|_| format!("bad batch '{}'", value))?,
+            "--rate" => options.train.learning_rate = value.parse().map_err(|_| format!("bad rate '{}'", value))?,
+            "--losses" => options.losses = Some(value.clone()),
+            "--openings" => options.arena.opening_plies = value.parse().map_err(|_| format!("bad openings '{}'", value))?,
+            "--threshold" => options.arena.threshold = value.parse().map_err(|_| format!("bad threshold '{}'", value))?,
+            "--log" => options.log = Some(value.clone()),
+            "--promote" => options.promote = Some(value.clone()),
+            "--network" => options.network = Some(value.clone()),
+            "--clock" => options.clock = Some(value.parse()?),
+            "--iterations" => options.iterations = Some(value.parse().map_err(|_| format!("bad iterations '{}'", value))?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    Ok(options)
+}
+
+/// The network of `--network`, checked against the board it plays on.
+fn load_network(options: &Options, board_size: usize) -> Result<Option<ResNet>, String> {
+    let Some(path) = &options.network else {
+        return Ok(None);
+    };
+
+    let network = ResNet::load(path).map_err(|e| format!("{}: {}", path, e))?;
+    if network.get_config().board_size != board_size {
+        return Err(format!("{} is for a board of {}", path, network.get_config().board_size));
+    }
+    Ok(Some(network))
+}
+
+fn new_game(options: &Options) -> Result<GameManager, String> {
+    let mut game_manager = match &options.fen {
+        Some(fen) => GameManager::from_fen(options.variant, fen).map_err(|e| e.to_string())?,
+        None => GameManager::with_variant(options.variant),
+    };
+
+    game_manager.set_band_player(BandPlayer { red: options.red.clone(), black: options.black.clone() });
+    if let Some(network) = load_network(options, game_manager.get_board_size())? {
+        let mut config = PuctConfig::default();
+        if let Some(iterations) = options.iterations {
+            config.simulations = iterations;
+        }
+        game_manager.set_brain(Puct::new(Arc::new(network), config));
+    } else if let Some(iterations) = options.iterations {
+        game_manager.set_brain(Mcts::with_iterations(iterations));
+    }
+    if let Some(control) = options.clock {
+        game_manager.set_clock(Clock::new(control));
+    }
+
+    Ok(game_manager)
+}
+
+fn current_player(game_manager: &GameManager) -> GamePlayer {
+    match game_manager.get_turn() {
+        Turn::Red => game_manager.get_band_player().red.clone(),
+        Turn::Black => game_manager.get_band_player().black.clone(),
+    }
+}
+
+fn play(options: &Options) -> Result<(), String> {
+    let mut game_manager = new_game(options)?;
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    println!("{}", HELP);
+    loop {
+        game_manager.print_board();
+
+        let status = game_manager.check_clock();
+        if status != GameStatus::Playing {
+            match status {
+                GameStatus::RedWins => println!("Red wins!!"),
+                GameStatus::BlackWins => println!("Black wins!!"),
+                GameStatus::Draw(reason) => println!("Draw by {}!!", reason),
+                GameStatus::LostOnTime(turn) => println!("{:?} lost on time!!", turn),
+                GameStatus::Playing => {}
+            }
+            println!("{}", game_manager.to_pdn(&PdnHeaders::default()));
+            return Ok(());
+        }
+
+        let turn = game_manager.get_turn();
+        if current_player(&game_manager) == GamePlayer::Computer {
+            let m = game_manager.computer_plays().ok_or("the computer found no move")?;
+            let text = game_manager.get_move_text(&m);
+            println!("{:?} plays {}", turn, text);
+            match game_manager.try_move(&m) {
+                // out of time, reported at the top of the loop
+                Ok(()) | Err(MoveError::GameOver(_)) => continue,
+                Err(error) => return Err(format!("the computer played {}: {}", text, error)),
+            }
+        }
+
+        if let Some(clock) = game_manager.get_clock() {
+            print!("Red {} Black {} ", clock::format_time(clock.get_remaining(Turn::Red)),
+                   clock::format_time(clock.get_remaining(Turn::Black)));
+        }
+        print!("{:?}> ", turn);
+        io::stdout().flush().map_err(|e| e.to_string())?;
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line.map_err(|e| e.to_string())?;
+
+        match line.trim() {
+            "" => {}
+            "help" => println!("{}", HELP),
+            "quit" => return Ok(()),
+            "fen" => println!("{}", game_manager.to_fen()),
+            "pdn" => println!("{}", game_manager.to_pdn(&PdnHeaders::default())),
+            "moves" => {
+                let moves = game_manager.get_legal_moves().clone();
+                let texts: Vec<String> = moves.iter().map(|m| game_manager.get_move_text(m)).collect();
+                println!("{}", texts.join(" "));
+            }
+            "undo" => {
+                // take back the computer answers too, up to the last human move
+                while game_manager.undo_move().is_some() && current_player(&game_manager) == GamePlayer::Computer {}
+            }
+            text => {
+                let m = text.parse::<MoveText>()
+                    .ok()
+                    .and_then(|move_text| move_text.to_move(turn, game_manager.get_board_size()));
+                match m {
+                    Some(m) => {
+                        if let Err(error) = game_manager.try_move(&m) {
+                            println!("{}: {}", text, error);
+                        }
+                    }
+                    None => println!("unknown command '{}', type help", text),
+                }
+            }
+        }
+    }
+}
+
+/// Speaks the text engine protocol on stdin and stdout.
+fn engine(options: &Options) -> Result<(), String> {
+    let game_manager = new_game(options)?;
+    let output: text::Output = Arc::new(Mutex::new(io::stdout()));
+
+    TextEngine::new(game_manager, output).run(io::stdin().lock()).map_err(|e| e.to_string())
+}
+
+/// Speaks the Hub protocol on stdin and stdout, for Scan compatible GUIs.
+fn hub(options: &Options) -> Result<(), String> {
+    let game_manager = new_game(options)?;
+    let output: text::Output = Arc::new(Mutex::new(io::stdout()));
+
+    HubEngine::new(game_manager, output).run(io::stdin().lock()).map_err(|e| e.to_string())
+}
+
+/// Plays one DXP game, offering it with `connect` or waiting for it with `listen`.
+fn dxp(options: &Options) -> Result<(), String> {
+    let [mode, address] = options.positional.as_slice() else {
+        return Err("dxp needs listen|connect and an address".to_string());
+    };
+
+    let stream = match mode.as_str() {
+        "listen" => TcpListener::bind(address).and_then(|listener| listener.accept()).map(|(stream, _)| stream),
+        "connect" => TcpStream::connect(address),
+        _ => return Err(format!("unknown dxp mode '{}'", mode)),
+    }.map_err(|e| e.to_string())?;
+
+    let mut connection = DxpConnection::new(stream).map_err(|e| e.to_string())?;
+    connection.set_log(|line| println!("DXP {}", line));
+    let game_manager = new_game(options)?;
+    let name = "Dark Queen Zer0";
+
+    let status = if mode == "listen" {
+        dxp::follow(&mut connection, game_manager, name)
+    } else {
+        let config = DxpConfig { name: name.to_string(), color: options.color, minutes: options.minutes, moves: options.moves };
+        dxp::initiate(&mut connection, game_manager, &config)
+    }.map_err(|e| e.to_string())?;
+
+    println!("{:?}", status);
+    Ok(())
+}
+
+/// Plays games of the network against itself and writes what it saw to a file for training.
+fn self_play(options: &Options) -> Result<(), String> {
+    let path = options.positional.first().ok_or("missing self-play file")?;
+    let board_size = options.variant.rules().board_size;
+    let network: Arc<dyn Network> = match load_network(options, board_size)? {
+        Some(network) => Arc::new(network),
+        None => Arc::new(UniformNetwork::new(board_size)),
+    };
+
+    let mut config = SelfPlayConfig::new(options.variant, options.games);
+    config.seed = options.seed;
+    if let Some(threads) = options.threads {
+        config.threads = threads;
+    }
+    if let Some(iterations) = options.iterations {
+        config.puct.simulations = iterations;
+    }
+
+    let samples = selfplay::generate(network, &config, |game, status, positions| {
+        println!("game {} {:?} after {} moves", game + 1, status, positions);
+    });
+
+    let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut writer = io::BufWriter::new(file);
+    selfplay::write_samples(&mut writer, board_size, &samples)
+        .and_then(|_| writer.flush())
+        .map_err(|e| format!("{}: {}", path, e))?;
+    println!("{} positions written to {}", samples.len(), path);
+    Ok(())
+}
+
+/// Trains a network on self-play files, saving it to the checkpoint after every epoch.
+fn train_network(options: &Options) -> Result<(), String> {
+    let [checkpoint, files @ ..] = options.positional.as_slice() else {
+        return Err("missing checkpoint".to_string());
+    };
+    if files.is_empty() {
+        return Err("missing self-play files".to_string());
+    }
+
+    let mut board_size = None;
+    let mut samples = Vec::new();
+    for path in files {
+        let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let (size, read) = selfplay::read_samples(&mut io::BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))?;
+        if board_size.is_some_and(|board_size| board_size != size) {
+            return Err(format!("{} is for a board of {}", path, size));
+        }
+        board_size = Some(size);
+        samples.extend(read);
+    }
+    let board_size = board_size.unwrap_or_default();
+
+    let network = match load_network(options, board_size)? {
+        Some(network) => network,
+        None => ResNet::new(ResNetConfig::small(board_size)).map_err(|e| e.to_string())?,
+    };
+    let mut losses = match &options.losses {
+        Some(path) => Some(std::fs::OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| format!("{}: {}", path, e))?),
+        None => None,
+    };
+
+    println!("training on {} positions", samples.len());
+    train::train(&network, &samples, &options.train, |epoch| {
+        println!("epoch {} policy {:.4} value {:.4} total {:.4}", epoch.epoch, epoch.policy, epoch.value, epoch.get_total());
+        if let Some(file) = losses.as_mut() {
+            writeln!(file, "{},{},{},{}", epoch.epoch, epoch.policy, epoch.value, epoch.get_total())?;
+        }
+        network.save(checkpoint)
+    }).map_err(|e| e.to_string())?;
+
+    println!("network saved to {}", checkpoint);
+    Ok(())
+}
+
+/// `mcts` or a network checkpoint, as a brain made fresh for every arena game.
+fn arena_player(name: &str, options: &Options) -> Result<Box<dyn Fn() -> Box<dyn Brain> + Sync>, String> {
+    let iterations = options.iterations;
+    if name == "mcts" {
+        return Ok(Box::new(move || match iterations {
+            Some(iterations) => Box::new(Mcts::with_iterations(iterations)) as Box<dyn Brain>,
+            None => Box::new(Mcts::new()),
+        }));
+    }
+
+    let network = ResNet::load(name).map_err(|e| format!("{}: {}", name, e))?;
+    if network.get_config().board_size != options.variant.rules().board_size {
+        return Err(format!("{} is for a board of {}", name, network.get_config().board_size));
+    }
+    let network: Arc<dyn Network> = Arc::new(network);
+    let mut config = PuctConfig::default();
+    if let Some(iterations) = iterations {
+        config.simulations = iterations;
+    }
+    Ok(Box::new(move || Box::new(Puct::new(network.clone(), config)) as Box<dyn Brain>))
+}
+
+/// Plays a candidate against the champion and promotes it if it scores enough.
+fn arena(options: &Options) -> Result<(), String> {
+    let [candidate_name, champion_name] = options.positional.as_slice() else {
+        return Err("arena needs a candidate and a champion".to_string());
+    };
+    let candidate = arena_player(candidate_name, options)?;
+    let champion = arena_player(champion_name, options)?;
+
+    let mut config = options.arena;
+    config.variant = options.variant;
+    config.games = options.games;
+    config.seed = options.seed;
+    if let Some(threads) = options.threads {
+        config.threads = threads;
+    }
+
+    let result = arena::play_match(candidate.as_ref(), champion.as_ref(), &config, |game, color, status| {
+        println!("game {} candidate {:?} {:?}", game + 1, color, status);
+    });
+    let promoted = result.passes(config.threshold);
+    println!("{} vs {}: {}", candidate_name, champion_name, result);
+
+    if let Some(path) = &options.log {
+        let seconds = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let (low, high) = result.get_interval();
+        let line = format!("{},{},{},{},{},{},{},{:.4},{:.4},{:.4},{:.2},{}",
+                           seconds, candidate_name, champion_name, config.variant, result.wins, result.draws,
+                           result.losses, result.get_score(), low, high, config.threshold, promoted);
+        std::fs::OpenOptions::new().create(true).append(true).open(path)
+            .and_then(|mut file| {
+                if file.metadata()?.len() == 0 {
+                    writeln!(file, "time,candidate,champion,variant,wins,draws,losses,score,low,high,threshold,promoted")?;
+                }
+                writeln!(file, "{}", line)
+            })
+            .map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    if !promoted {
+        println!("the candidate stays out, it needed {:.3}", config.threshold);
+    } else if let Some(path) = &options.promote {
+        std::fs::copy(candidate_name, path).map_err(|e| format!("{}: {}", path, e))?;
+        println!("the candidate is promoted to {}", path);
+    } else {
+        println!("the candidate is promoted");
+    }
+    Ok(())
+}
+
+fn perft(options: &Options) -> Result<(), String> {
+    let depth = options.positional.first()
+        .ok_or("missing perft depth")?
+        .parse()
+        .map_err(|_| "bad perft depth".to_string())?;
+
+    let mut game_manager = new_game(options)?;
+    println!("{}", game_manager.to_fen());
+    let start = std::time::Instant::now();
+    let divide = game_manager.perft_divide(depth);
+    let elapsed = start.elapsed();
+
+    for (m, nodes) in &divide {
+        println!("{}: {}", game_manager.get_move_text(m), nodes);
+    }
+    let nodes: u64 = divide.iter().map(|(_, nodes)| nodes).sum();
+    println!("Total: {}", nodes);
+    println!("Time: {:.3}s, {:.0} nodes/s", elapsed.as_secs_f64(), nodes as f64 / elapsed.as_secs_f64());
+    Ok(())
+}
+
+/// Replays every game of a PDN file and prints where it ends.
+fn replay_pdn(options: &Options) -> Result<(), String> {
+    let path = options.positional.first().ok_or("missing PDN file")?;
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+
+    for game in pdn::parse_pdn(&text).map_err(|e| e.to_string())? {
+        match game.replay() {
+            Ok(game_manager) => println!("{} {}", game_manager.to_fen(), game.result),
+            Err(e) => println!("{}", e),
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(command) = args.first() else {
+        println!("{}", USAGE);
+        return;
+    };
+
+    let result = parse_options(&args[1..]).and_then(|options| match command.as_str() {
+        "play" => play(&options),
+        "engine" => engine(&options),
+        "hub" => hub(&options),
+        "dxp" => dxp(&options),
+        "selfplay" => self_play(&options),
+        "train" => train_network(&options),
+        "arena" => arena(&options),
+        "perft" => perft(&options),
+        "pdn" => replay_pdn(&options),
+        _ => Err(USAGE.to_string()),
+    });
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,354 @@
+use crate::comun::{BLACK_PAWN, BLACK_QUEEN, Move, Square, Turn, WHITE_PAWN, WHITE_QUEEN};
+use crate::rules::{PromotionDuringCapture, RuleSet};
+
+/// Side of the board the bitboards can represent.
+pub const BITBOARD_SIZE: usize = 8;
+
+const ALL_SQUARES: u32 = 0xFFFF_FFFF;
+const EVEN_ROWS: u32 = 0x0F0F_0F0F;
+const ODD_ROWS: u32 = 0xF0F0_F0F0;
+/// Column 0, only playable on even rows.
+const LEFT_EDGE: u32 = 0x0101_0101;
+/// Column 7, only playable on odd rows.
+const RIGHT_EDGE: u32 = 0x8080_8080;
+const FIRST_ROW: u32 = 0x0000_000F;
+const LAST_ROW: u32 = 0xF000_0000;
+
+/// Diagonal steps as (row, col) deltas, in the same order the shifts are implemented.
+const DIRECTIONS: [(isize, isize); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+/// An 8x8 position as 32-bit masks of the playable squares, one per side and piece kind.
+/// Square `(row, col)` is bit `row * 4 + col / 2`, so moving one row up is a shift of 3, 4
+/// or 5 bits depending on the row parity and the column.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Bitboards {
+    pub red_pawns: u32,
+    pub red_queens: u32,
+    pub black_pawns: u32,
+    pub black_queens: u32,
+}
+
+pub fn square_to_bit(square: Square) -> u32 {
+    1 << (square.row * 4 + square.col / 2)
+}
+
+pub fn bit_to_square(index: u32) -> Square {
+    let row = (index / 4) as usize;
+    let col = (index % 4) as usize * 2 + row % 2;
+    Square { row, col }
+}
+
+/// Moves every square of `bb` one step along `direction`, dropping those that leave the board.
+fn shift(bb: u32, direction: (isize, isize)) -> u32 {
+    match direction {
+        (1, 1) => ((bb & EVEN_ROWS) << 4) | ((bb & ODD_ROWS & !RIGHT_EDGE) << 5),
+        (1, -1) => ((bb & EVEN_ROWS & !LEFT_EDGE) << 3) | ((bb & ODD_ROWS) << 4),
+        (-1, 1) => ((bb & EVEN_ROWS) >> 4) | ((bb & ODD_ROWS & !RIGHT_EDGE) >> 3),
+        (-1, -1) => ((bb & EVEN_ROWS & !LEFT_EDGE) >> 5) | ((bb & ODD_ROWS) >> 4),
+        _ => 0,
+    }
+}
+
+fn bits(mut bb: u32) -> impl Iterator<Item = u32> {
+    std::iter::from_fn(move || {
+        if bb == 0 {
+            return None;
+        }
+        let index = bb.trailing_zeros();
+        bb &= bb - 1;
+        Some(index)
+    })
+}
+
+impl Bitboards {
+    pub fn from_board(board: &[Vec<i8>]) -> Bitboards {
+        let mut bitboards = Bitboards::default();
+
+        for (row, cells) in board.iter().enumerate() {
+            for (col, &piece) in cells.iter().enumerate() {
+                if (row + col) % 2 == 0 {
+                    bitboards.set(Square { row, col }, piece);
+                }
+            }
+        }
+
+        bitboards
+    }
+
+    pub fn set(&mut self, square: Square, piece: i8) {
+        let bit = square_to_bit(square);
+
+        self.red_pawns &= !bit;
+        self.red_queens &= !bit;
+        self.black_pawns &= !bit;
+        self.black_queens &= !bit;
+
+        match piece {
+            WHITE_PAWN => self.red_pawns |= bit,
+            WHITE_QUEEN => self.red_queens |= bit,
+            BLACK_PAWN => self.black_pawns |= bit,
+            BLACK_QUEEN => self.black_queens |= bit,
+            _ => {}
+        }
+    }
+
+    pub fn red(&self) -> u32 { self.red_pawns | self.red_queens }
+
+    pub fn black(&self) -> u32 { self.black_pawns | self.black_queens }
+
+    pub fn empty(&self) -> u32 { ALL_SQUARES & !(self.red() | self.black()) }
+
+    /// Pawns, queens, opponent pawns and opponent queens of the side to move.
+    fn sides(&self, turn: Turn) -> (u32, u32, u32, u32) {
+        match turn {
+            Turn::Red => (self.red_pawns, self.red_queens, self.black_pawns, self.black_queens),
+            Turn::Black => (self.black_pawns, self.black_queens, self.red_pawns, self.red_queens),
+        }
+    }
+
+    fn forward(turn: Turn) -> isize {
+        match turn {
+            Turn::Red => 1,
+            Turn::Black => -1,
+        }
+    }
+
+    /// True when the side to move has at least one capture available.
+    pub fn has_captures(&self, turn: Turn, rules: &RuleSet) -> bool {
+        let (pawns, queens, opponent_pawns, opponent_queens) = self.sides(turn);
+        let opponents = opponent_pawns | opponent_queens;
+        let pawn_targets = if rules.pawns_capture_queens { opponents } else { opponent_pawns };
+        let empty = self.empty();
+
+        for direction in DIRECTIONS {
+            let pawns_can_jump = direction.0 == Self::forward(turn) || rules.pawns_capture_backward;
+            if pawns_can_jump && shift(shift(pawns, direction) & pawn_targets, direction) & empty != 0 {
+                return true;
+            }
+
+            // queens slide through empty squares until they meet a piece
+            let mut sliders = queens;
+            while sliders != 0 {
+                let next = shift(sliders, direction);
+                if shift(next & opponents, direction) & empty != 0 {
+                    return true;
+                }
+                if !rules.flying_queens {
+                    break;
+                }
+                sliders = next & empty;
+            }
+        }
+
+        false
+    }
+
+    /// Every non capturing move of the side to move.
+    pub fn get_quiet_moves(&self, turn: Turn, rules: &RuleSet) -> Vec<Move> {
+        let (pawns, queens, _, _) = self.sides(turn);
+        let empty = self.empty();
+        let forward = Self::forward(turn);
+        let last_row = if turn == Turn::Red { LAST_ROW } else { FIRST_ROW };
+        let mut moves = Vec::new();
+
+        for direction in DIRECTIONS.into_iter().filter(|direction| direction.0 == forward) {
+            for index in bits(shift(pawns, direction) & empty) {
+                let to = bit_to_square(index);
+                let from = Square {
+                    row: to.row.wrapping_add_signed(-direction.0),
+                    col: to.col.wrapping_add_signed(-direction.1),
+                };
+                let promotion = (1 << index) & last_row != 0;
+                moves.push(Move { turn, from, to, path: vec![to], eat: Vec::new(), promotion });
+            }
+        }
+
+        for index in bits(queens) {
+            let from = bit_to_square(index);
+            for direction in DIRECTIONS {
+                let mut ray = shift(1 << index, direction) & empty;
+                while ray != 0 {
+                    let to = bit_to_square(ray.trailing_zeros());
+                    moves.push(Move { turn, from, to, path: vec![to], eat: Vec::new(), promotion: false });
+                    if !rules.flying_queens {
+                        break;
+                    }
+                    ray = shift(ray, direction) & empty;
+                }
+            }
+        }
+
+        moves
+    }
+
+    /// Squares of the side to move holding a piece that can start a capture.
+    pub fn get_capturers(&self, turn: Turn, rules: &RuleSet) -> Vec<Square> {
+        let (pawns, queens, opponent_pawns, opponent_queens) = self.sides(turn);
+        let opponents = opponent_pawns | opponent_queens;
+        let pawn_targets = if rules.pawns_capture_queens { opponents } else { opponent_pawns };
+        let empty = self.empty();
+        let mut capturers = 0;
+
+        for direction in DIRECTIONS {
+            let back = (-direction.0, -direction.1);
+
+            if direction.0 == Self::forward(turn) || rules.pawns_capture_backward {
+                let landings = shift(shift(pawns, direction) & pawn_targets, direction) & empty;
+                capturers |= shift(shift(landings, back), back) & pawns;
+            }
+
+            for index in bits(queens) {
+                let mut next = shift(1 << index, direction);
+                while rules.flying_queens && next & empty != 0 {
+                    next = shift(next, direction);
+                }
+                if shift(next & opponents, direction) & empty != 0 {
+                    capturers |= 1 << index;
+                }
+            }
+        }
+
+        bits(capturers).map(bit_to_square).collect()
+    }
+
+    /// Piece on the square of `bit`, which must hold one.
+    fn piece_at(&self, bit: u32) -> i8 {
+        if self.red_pawns & bit != 0 {
+            WHITE_PAWN
+        } else if self.red_queens & bit != 0 {
+            WHITE_QUEEN
+        } else if self.black_pawns & bit != 0 {
+            BLACK_PAWN
+        } else {
+            BLACK_QUEEN
+        }
+    }
+
+    /// Every finished capture of the side to move, one compound move per chain.
+    pub fn get_captures(&self, turn: Turn, rules: &RuleSet) -> Vec<Move> {
+        let (_, queens, _, _) = self.sides(turn);
+        let mut moves = Vec::new();
+
+        for from in self.get_capturers(turn, rules) {
+            let bit = square_to_bit(from);
+            let chain = Chain {
+                bitboards: self,
+                turn,
+                rules,
+                from,
+                from_queen: queens & bit != 0,
+                // the capturing piece leaves its square while the chain is explored
+                empty: self.empty() | bit,
+            };
+            chain.extend(bit, chain.from_queen, 0, &mut Vec::new(), &mut Vec::new(), &mut moves);
+        }
+
+        moves
+    }
+
+    pub fn count(&self) -> (u32, u32, u32, u32) {
+        (self.red_pawns.count_ones(), self.red_queens.count_ones(), self.black_pawns.count_ones(), self.black_queens.count_ones())
+    }
+}
+
+/// A capture being explored from `from`. Captured pieces stay on the board until the move is
+/// done, so `empty` never changes: they block the way and cannot be jumped twice.
+struct Chain<'a> {
+    bitboards: &'a Bitboards,
+    turn: Turn,
+    rules: &'a RuleSet,
+    from: Square,
+    from_queen: bool,
+    empty: u32,
+}
+
+impl Chain<'_> {
+    /// Pieces a pawn or a queen may jump, leaving out those already captured.
+    fn targets(&self, queen: bool, captured: u32) -> u32 {
+        let (_, _, opponent_pawns, opponent_queens) = self.bitboards.sides(self.turn);
+        let targets = if queen || self.rules.pawns_capture_queens { opponent_pawns | opponent_queens } else { opponent_pawns };
+        targets & !captured
+    }
+
+    /// Single jumps from `at` as (captured bit, landing bit), nearest landing first.
+    fn jumps(&self, at: u32, queen: bool, targets: u32) -> Vec<(u32, u32)> {
+        let flying = queen && self.rules.flying_queens;
+        let mut jumps = Vec::new();
+
+        for direction in DIRECTIONS {
+            if !queen && !self.rules.pawns_capture_backward && direction.0 != Bitboards::forward(self.turn) {
+                continue;
+            }
+
+            let mut over = shift(at, direction);
+            while flying && over & self.empty != 0 {
+                over = shift(over, direction);
+            }
+            if over & targets == 0 {
+                continue;
+            }
+
+            let mut landing = shift(over, direction) & self.empty;
+            while landing != 0 {
+                jumps.push((over, landing));
+                if !flying {
+                    break;
+                }
+                landing = shift(landing, direction) & self.empty;
+            }
+        }
+
+        jumps
+    }
+
+    fn extend(&self, at: u32, queen: bool, captured: u32, path: &mut Vec<Square>,
+              eat: &mut Vec<(Square, i8)>, moves: &mut Vec<Move>) {
+        let mut queen = queen;
+        let last_row = if self.turn == Turn::Red { LAST_ROW } else { FIRST_ROW };
+        let to = bit_to_square(at.trailing_zeros());
+
+        if !eat.is_empty() && !queen && at & last_row != 0 {
+            match self.rules.promotion_during_capture {
+                PromotionDuringCapture::Stop => {
+                    moves.push(Move { turn: self.turn, from: self.from, to, path: path.clone(), eat: eat.clone(), promotion: true });
+                    return;
+                }
+                PromotionDuringCapture::ContinueAsQueen => queen = true,
+                PromotionDuringCapture::ContinueAsPawn => {}
+            }
+        }
+
+        let mut jumps = self.jumps(at, queen, self.targets(queen, captured));
+
+        // a flying queen has to land where the capture goes on, if any of the squares allows it
+        if queen && self.rules.flying_queens {
+            let continues: Vec<bool> = jumps.iter()
+                .map(|&(over, landing)| !self.jumps(landing, queen, self.targets(queen, captured | over)).is_empty())
+                .collect();
+            let continuing = jumps.iter().zip(&continues)
+                .filter(|(_, &continues)| continues)
+                .fold(0, |mask, ((over, _), _)| mask | over);
+            jumps = jumps.into_iter()
+                .zip(continues)
+                .filter(|((over, _), continues)| *continues || continuing & over == 0)
+                .map(|(jump, _)| jump)
+                .collect();
+        }
+
+        if jumps.is_empty() {
+            if !eat.is_empty() {
+                let promotion = !self.from_queen && (queen || at & last_row != 0);
+                moves.push(Move { turn: self.turn, from: self.from, to, path: path.clone(), eat: eat.clone(), promotion });
+            }
+            return;
+        }
+
+        for (over, landing) in jumps {
+            path.push(bit_to_square(landing.trailing_zeros()));
+            eat.push((bit_to_square(over.trailing_zeros()), self.bitboards.piece_at(over)));
+            self.extend(landing, queen, captured | over, path, eat, moves);
+            path.pop();
+            eat.pop();
+        }
+    }
+}
@@ -11,19 +11,23 @@ use bevy::{
 };
 use bevy::app::AppExit;
 use bevy::asset::{AssetServer, Handle};
-use bevy::prelude::{AlignItems, BackgroundColor, BuildChildren, ButtonBundle, Changed, DespawnRecursiveExt, Entity, EventWriter, FlexDirection, Image, Interaction, JustifyContent, NodeBundle, Query, Res, ResMut, Resource, TextBundle, TextStyle, UiRect, With, Without};
+use bevy::log::{info, warn};
+use bevy::prelude::{AlignItems, Deref, DerefMut, BackgroundColor, BuildChildren, ButtonBundle, Changed, DespawnRecursiveExt, Entity, EventWriter, FlexDirection, Image, Interaction, JustifyContent, NodeBundle, Query, Res, ResMut, Resource, Text, TextBundle, TextStyle, UiRect, With, Without};
 use bevy::ui::{PositionType, Style, Val};
 use bevy::window::Window;
 use bevy_mod_picking::events::{Click, Pointer};
 use bevy_mod_picking::PickableBundle;
 use bevy_mod_picking::prelude::{Listener, On};
 
-use crate::comun::{BLACK_PAWN, BOARD_SIZE, GamePlayer, GameStatus, Move, Square, Turn, WHITE_PAWN};
+use dark_queen_zer0::clock;
+use dark_queen_zer0::comun::{BLACK_PAWN, BLACK_QUEEN, GamePlayer, GameStatus, Move, Square, Turn, WHITE_PAWN, WHITE_QUEEN};
+use dark_queen_zer0::pdn::PdnHeaders;
 use crate::GameManagerResource;
 
-const CELL_SIZE: f32 = 70.0;
-const BOARD_X_OFFSET: f32 = -245.0;
-const BOARD_Y_OFFSET: f32 = -265.0; // Desplazamiento para centrar el tablero en la pantalla
+const BOARD_WIDTH: f32 = 560.0;
+const BOARD_X_OFFSET: f32 = -280.0;
+const BOARD_Y_OFFSET: f32 = -300.0; // Desplazamiento para centrar el tablero en la pantalla
+const PIECE_SCALE_PER_PIXEL: f32 = 0.4 / 70.0;
 
 const WHITE_PIECE_PATH: &str = "models/pieces/Roja.png";
 const BLACK_PIECE_PATH: &str = "models/pieces/Negra.png";
@@ -35,6 +39,10 @@ pub struct HighlightedSquare {
     squares: Vec<Square>,
 }
 
+/// The engine square of a board tile or piece. `Square` itself stays free of Bevy.
+#[derive(Component, Deref, DerefMut, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BoardSquare(pub Square);
+
 #[derive(Component, Debug, Default)]
 pub struct Piece {
     pub piece_type: i8,
@@ -60,13 +68,49 @@ pub struct PieceIdByEntity(HashMap<usize, Entity>);
 pub struct WinnerWindow;
 #[derive(Component)]
 pub struct AcceptButton;
+#[derive(Component)]
+pub struct ClockText;
+
+fn cell_size(board_size: usize) -> f32 {
+    BOARD_WIDTH / board_size as f32
+}
+
+// Centro de la casilla en pantalla
+fn square_position(row: usize, col: usize, board_size: usize) -> (f32, f32) {
+    let cell_size = cell_size(board_size);
+    let x = (col as f32 + 0.5) * cell_size + BOARD_X_OFFSET;
+    let y = (row as f32 + 0.5) * cell_size + BOARD_Y_OFFSET;
+    (x, y)
+}
 
 // Función para inicializar el tablero
-pub fn setup_board(mut commands: Commands) {
-    for row in 0..BOARD_SIZE {
-        for col in 0..BOARD_SIZE {
-            let x = col as f32 * CELL_SIZE + BOARD_X_OFFSET;
-            let y = row as f32 * CELL_SIZE + BOARD_Y_OFFSET;
+pub fn setup_board(mut commands: Commands, asset_server: Res<AssetServer>, game_manager: Res<GameManagerResource>) {
+    let board_size = game_manager.0.get_board_size();
+    let cell_size = cell_size(board_size);
+
+    // the clocks go in the strip above the board
+    if game_manager.0.get_clock().is_some() {
+        commands.spawn((
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                },
+            ).with_style(Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(20.0),
+                top: Val::Px(20.0),
+                ..default()
+            }),
+            ClockText,
+        ));
+    }
+
+    for row in 0..board_size {
+        for col in 0..board_size {
+            let (x, y) = square_position(row, col, board_size);
 
             commands.spawn((SpriteBundle {
                 sprite: Sprite {
@@ -75,13 +119,13 @@ pub fn setup_board(mut commands: Commands) {
                     } else {
                         Color::BEIGE
                     },
-                    custom_size: Some(Vec2::new(CELL_SIZE, CELL_SIZE)),
+                    custom_size: Some(Vec2::new(cell_size, cell_size)),
                     ..default()
                 },
                 transform: Transform::from_xyz(x, y, 0.),
                 ..Default::default()
             },
-                            Square { row, col },
+                            BoardSquare(Square { row, col }),
                             PickableBundle::default(),
                             On::<Pointer<Click>>::run(square_click_event)
             ));
@@ -94,60 +138,50 @@ pub fn create_pieces(mut commands: Commands, asset_server: Res<AssetServer>, gam
     let black_piece: Handle<Image> = asset_server.load(BLACK_PIECE_PATH);
 
     let board = game_manager.0.get_board();
-
-    for row in 0..BOARD_SIZE {
-        for col in 0..BOARD_SIZE {
-            let x = col as f32 * CELL_SIZE + BOARD_X_OFFSET;
-            let y = row as f32 * CELL_SIZE + BOARD_Y_OFFSET;
-
-            println!("Board[{}][{}]: {}", row, col, board[row][col]);
-
-            let piece = board[row][col];
-            let tupla = match piece {
-                WHITE_PAWN => Some((SpriteBundle {
-                    texture: white_piece.clone(),
-                    transform: {
-                        let mut transform = Transform::from_xyz(x, y, 0.1);
-                        transform.scale = Vec3::splat(0.4);
-                        transform
-                    },
-                    ..Default::default()
-                },
-                                              Square { row, col },
-                                              Piece{ piece_type: WHITE_PAWN, id: (row * 10) + col },
-                                              PickableBundle::default(),
-                                              On::<Pointer<Click>>::run(piece_click_event)
-                )),
-                BLACK_PAWN => Some((SpriteBundle {
-                    texture: black_piece.clone(),
-                    transform: {
-                        let mut transform = Transform::from_xyz(x, y, 0.1);
-                        transform.scale = Vec3::splat(0.4);
-                        transform
-                    },
-                    ..Default::default()
-                },
-                                              Square { row, col }, Piece{ piece_type: BLACK_PAWN, id: (row * 10) + col },
-                                              PickableBundle::default(), On::<Pointer<Click>>::run(piece_click_event)
-                )),
-                _ => None
+    let board_size = game_manager.0.get_board_size();
+    let piece_scale = Vec3::splat(cell_size(board_size) * PIECE_SCALE_PER_PIXEL);
+
+    for (row, board_row) in board.iter().enumerate() {
+        for (col, &piece) in board_row.iter().enumerate() {
+            let (x, y) = square_position(row, col, board_size);
+            let id = row * board_size + col;
+
+            let texture = match piece {
+                WHITE_PAWN => white_piece.clone(),
+                BLACK_PAWN => black_piece.clone(),
+                // positions loaded from FEN may already have queens
+                WHITE_QUEEN => asset_server.load(WHITE_QUEEN_PATH),
+                BLACK_QUEEN => asset_server.load(BLACK_QUEEN_PATH),
+                _ => continue,
             };
 
-            if let Some(tupla) = tupla {
-                let id = commands.spawn(tupla).id();
-                square_by_id.0.insert((row * 10 ) + col, id);
-            }
+            let entity = commands.spawn((SpriteBundle {
+                texture,
+                transform: {
+                    let mut transform = Transform::from_xyz(x, y, 0.1);
+                    transform.scale = piece_scale;
+                    transform
+                },
+                ..Default::default()
+            },
+                                         BoardSquare(Square { row, col }),
+                                         Piece{ piece_type: piece, id },
+                                         PickableBundle::default(),
+                                         On::<Pointer<Click>>::run(piece_click_event)
+            )).id();
+            square_by_id.0.insert(id, entity);
         }
     }
 }
 
 
+#[allow(clippy::too_many_arguments)]
 fn square_click_event(
     event: Listener<Pointer<Click>>,
     asset_server: Res<AssetServer>,
-    mut transform_square_piece: Query<(&mut Handle<Image>, &mut Transform, &mut Square, &Piece)>,
-    empty_square: Query<&Square, Without<Piece>>,
-    mut squares_sprites: Query<(&mut Sprite, &Square), Without<Piece>>,
+    mut transform_square_piece: Query<(&mut Handle<Image>, &mut Transform, &mut BoardSquare, &Piece)>,
+    empty_square: Query<&BoardSquare, Without<Piece>>,
+    mut squares_sprites: Query<(&mut Sprite, &BoardSquare), Without<Piece>>,
     mut highlighted_square: ResMut<HighlightedSquare>,
     mut piece_clicked: ResMut<PieceClicked>,
     mut game_manager: ResMut<GameManagerResource>,
@@ -158,31 +192,30 @@ fn square_click_event(
     let square_clicked = empty_square.get(event.target).unwrap();
     //println!("Square clicked: {:?}", square_clicked);
 
-    if highlighted_square.squares.iter().any(|square| square.row == square_clicked.row && square.col == square_clicked.col) {
-        if piece_clicked.clicked {
-            //println!("Piece clicked: {:?}", piece_clicked.square);
-            piece_clicked.clicked = false;
+    if highlighted_square.squares.iter().any(|square| square.row == square_clicked.row && square.col == square_clicked.col)
+        && piece_clicked.clicked {
+        //println!("Piece clicked: {:?}", piece_clicked.square);
+        piece_clicked.clicked = false;
 
-            unhighlight_square(&mut squares_sprites, &mut highlighted_square.squares);
+        unhighlight_square(&mut squares_sprites, &mut highlighted_square.squares);
 
-            if let Some(move_to) = legal_moves_for_piece.moves.iter().find(|&m| m.to.row == square_clicked.row && m.to.col == square_clicked.col) {
-                move_piece(
-                    move_to,
-                    &mut transform_square_piece,
-                    &asset_server,
-                    &mut game_manager,
-                    &mut piece_by_id,
-                    &mut commands,
-                );
-            }
+        if let Some(move_to) = legal_moves_for_piece.moves.iter().find(|&m| m.to.row == square_clicked.row && m.to.col == square_clicked.col) {
+            move_piece(
+                move_to,
+                &mut transform_square_piece,
+                &asset_server,
+                &mut game_manager,
+                &mut piece_by_id,
+                &mut commands,
+            );
         }
     }
 }
 
 fn piece_click_event(event: Listener<Pointer<Click>>,
-                     pieces: Query<(&Square, &Piece)>,
+                     pieces: Query<(&BoardSquare, &Piece)>,
                      mut piece_clicked: ResMut<PieceClicked>,
-                     mut squares: Query<(&mut Sprite, &Square), Without<Piece>>,
+                     mut squares: Query<(&mut Sprite, &BoardSquare), Without<Piece>>,
                      mut game_manager: ResMut<GameManagerResource>,
                      mut highlighted_square: ResMut<HighlightedSquare>,
                      mut legal_moves_for_piece: ResMut<LegalMovesForPieceResource>
@@ -214,7 +247,7 @@ fn piece_click_event(event: Listener<Pointer<Click>>,
 
 fn move_piece(
     move_to: &Move,
-    transform_square_piece: &mut Query<(&mut Handle<Image>, &mut Transform, &mut Square, &Piece)>,
+    transform_square_piece: &mut Query<(&mut Handle<Image>, &mut Transform, &mut BoardSquare, &Piece)>,
     asset_server: &Res<AssetServer>,
     game_manager: &mut ResMut<GameManagerResource>,
     piece_by_id: &mut ResMut<PieceIdByEntity>,
@@ -229,11 +262,18 @@ fn move_piece(
     }
 
     if let Some((texture, mut transform, mut square_piece)) = found_piece {
-        let delta_x = move_to.to.col as f32 * CELL_SIZE + BOARD_X_OFFSET - transform.translation.x;
-        let delta_y = move_to.to.row as f32 * CELL_SIZE + BOARD_Y_OFFSET - transform.translation.y;
+        let (x, y) = square_position(move_to.to.row, move_to.to.col, game_manager.0.get_board_size());
+        let delta_x = x - transform.translation.x;
+        let delta_y = y - transform.translation.y;
+
+        let text = game_manager.0.get_move_text(move_to);
+        if let Err(error) = game_manager.0.try_move(move_to) {
+            warn!("{:?} cannot play {}: {}", move_to.turn, text, error);
+            return;
+        }
+        info!("{:?} plays {}", move_to.turn, text);
 
-        transform.translation = transform.translation + Vec3::new(delta_x, delta_y, 0.1);
-        game_manager.0.do_move(move_to.clone());
+        transform.translation += Vec3::new(delta_x, delta_y, 0.1);
 
         square_piece.row = move_to.to.row;
         square_piece.col = move_to.to.col;
@@ -249,8 +289,8 @@ fn move_piece(
             *texture = queen_texture;
         }
 
-        if let Some(square_eaten) = move_to.eat {
-            let id_opt = get_id_from_square(square_eaten.0, transform_square_piece);
+        for (square_eaten, _) in move_to.eat.iter() {
+            let id_opt = get_id_from_square(*square_eaten, transform_square_piece);
             if let Some(id) = id_opt {
                 if let Some(entity) = piece_by_id.0.get(&id) {
                     commands.entity(*entity).despawn();
@@ -261,17 +301,17 @@ fn move_piece(
     }
 }
 
-fn highlight_square(dest_square: &Square, sprite_square: &mut Query<(&mut Sprite, &Square), Without<Piece>>, highlighted_square: &mut Vec<Square>) {
+fn highlight_square(dest_square: &Square, sprite_square: &mut Query<(&mut Sprite, &BoardSquare), Without<Piece>>, highlighted_square: &mut Vec<Square>) {
     for (mut sprite, square) in sprite_square.iter_mut() {
         if square.row == dest_square.row && square.col == dest_square.col {
             sprite.color = Color::GREEN;
-            highlighted_square.push(dest_square.clone());
+            highlighted_square.push(*dest_square);
             break;
         }
     }
 }
 
-fn unhighlight_square(squares: &mut Query<(&mut Sprite, &Square), Without<Piece>>,  highlighted_square: &mut Vec<Square>) {
+fn unhighlight_square(squares: &mut Query<(&mut Sprite, &BoardSquare), Without<Piece>>,  highlighted_square: &mut Vec<Square>) {
     for highlitghted_square in highlighted_square.iter() {
         for (mut sprite, square) in squares.iter_mut() {
             if square.row == highlitghted_square.row && square.col == highlitghted_square.col {
@@ -282,7 +322,7 @@ fn unhighlight_square(squares: &mut Query<(&mut Sprite, &Square), Without<Piece>
     highlighted_square.clear();
 }
 
-fn get_id_from_square(square: Square, pieces: &mut Query<(&mut Handle<Image>, &mut Transform, &mut Square, &Piece)>) -> Option<usize> {
+fn get_id_from_square(square: Square, pieces: &mut Query<(&mut Handle<Image>, &mut Transform, &mut BoardSquare, &Piece)>) -> Option<usize> {
     for (_, _, s, piece) in pieces.iter() {
         if s.row == square.row && s.col == square.col {
             return Some(piece.id);
@@ -297,18 +337,33 @@ pub fn game_flow(
     mut game_manager: ResMut<GameManagerResource>,
     asset_server: Res<AssetServer>,
     query: Query<Entity, With<WinnerWindow>>,
-    mut transform_square_piece: Query<(&mut Handle<Image>, &mut Transform, &mut Square, &Piece)>,
+    mut transform_square_piece: Query<(&mut Handle<Image>, &mut Transform, &mut BoardSquare, &Piece)>,
     mut piece_by_id: ResMut<PieceIdByEntity>,
     mut window: Query<&mut Window>,
 ) {
-    if game_manager.0.get_game_status() != GameStatus::Playing {
+    if game_manager.0.check_clock() != GameStatus::Playing {
         let winner_name = match game_manager.0.get_game_status() {
-            GameStatus::RedWins => "Red wins!!",
-            GameStatus::BlackWins => "Black wins!!",
-            _ => "Draw!!",
+            GameStatus::RedWins => "Red wins!!".to_string(),
+            GameStatus::BlackWins => "Black wins!!".to_string(),
+            GameStatus::Draw(reason) => format!("Draw by {}!!", reason),
+            GameStatus::LostOnTime(turn) => format!("{:?} lost on time!!", turn),
+            GameStatus::Playing => String::new(),
         };
 
         if query.is_empty() {
+            let band_player = game_manager.0.get_band_player();
+            let player_name = |player: &GamePlayer| match player {
+                GamePlayer::Human => "Human".to_string(),
+                GamePlayer::Computer => "Dark Queen Zer0".to_string(),
+            };
+            let headers = PdnHeaders {
+                event: "Casual game".to_string(),
+                white: player_name(&band_player.red),
+                black: player_name(&band_player.black),
+                ..Default::default()
+            };
+            info!("{}", game_manager.0.to_pdn(&headers));
+
             commands
                 .spawn((
                     NodeBundle {
@@ -381,21 +436,35 @@ pub fn game_flow(
             let mut window = window.single_mut();
             window.cursor.icon = CursorIcon::Wait;
 
-            let movement = game_manager.0.computer_plays();
-            move_piece(
-                &movement,
-                &mut transform_square_piece,
-                &asset_server,
-                &mut game_manager,
-                &mut piece_by_id,
-                &mut commands,
-            );
+            if let Some(movement) = game_manager.0.computer_plays() {
+                move_piece(
+                    &movement,
+                    &mut transform_square_piece,
+                    &asset_server,
+                    &mut game_manager,
+                    &mut piece_by_id,
+                    &mut commands,
+                );
+            }
             
             window.cursor.icon = CursorIcon::Default;
         }
     }
 }
 
+pub fn update_clock(game_manager: Res<GameManagerResource>, mut texts: Query<&mut Text, With<ClockText>>) {
+    let Some(clock) = game_manager.0.get_clock() else {
+        return;
+    };
+
+    for mut text in &mut texts {
+        text.sections[0].value = format!("Red {}    Black {}",
+                                         clock::format_time(clock.get_remaining(Turn::Red)),
+                                         clock::format_time(clock.get_remaining(Turn::Black)));
+    }
+}
+
+#[allow(clippy::type_complexity)]
 pub fn button_system(
     mut interaction_query: Query<(&Interaction, &mut BackgroundColor), (Changed<Interaction>, With<AcceptButton>)>,
     mut exit: EventWriter<AppExit>,
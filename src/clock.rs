@@ -0,0 +1,268 @@
+//! Chess clocks for timed games.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::comun::Turn;
+
+/// Moves the remaining time is expected to last for when the control does not say.
+const MOVES_TO_GO: u32 = 30;
+
+/// How much time each side gets. Written like the PDN `TimeControl` tag, in seconds:
+/// `300` is sudden death, `300+5` adds 5 seconds after every move (Fischer), `300d5` gives
+/// back up to 5 seconds of every move (Bronstein) and `40/5400` gives 5400 seconds for every
+/// 40 moves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeControl {
+    SuddenDeath { time: Duration },
+    Fischer { time: Duration, increment: Duration },
+    Bronstein { time: Duration, delay: Duration },
+    MovesPerPeriod { time: Duration, moves: u32 },
+}
+
+impl TimeControl {
+    /// Time on each clock at the start of the game.
+    pub fn get_time(&self) -> Duration {
+        match *self {
+            TimeControl::SuddenDeath { time }
+            | TimeControl::Fischer { time, .. }
+            | TimeControl::Bronstein { time, .. }
+            | TimeControl::MovesPerPeriod { time, .. } => time,
+        }
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeControl::SuddenDeath { time } => write!(f, "{}", time.as_secs()),
+            TimeControl::Fischer { time, increment } => write!(f, "{}+{}", time.as_secs(), increment.as_secs()),
+            TimeControl::Bronstein { time, delay } => write!(f, "{}d{}", time.as_secs(), delay.as_secs()),
+            TimeControl::MovesPerPeriod { time, moves } => write!(f, "{}/{}", moves, time.as_secs()),
+        }
+    }
+}
+
+impl std::str::FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let number = |text: &str| text.parse::<u64>().map_err(|_| format!("bad time control '{}'", s));
+        let seconds = |text: &str| number(text).map(Duration::from_secs);
+
+        let control = if let Some((time, increment)) = s.split_once('+') {
+            TimeControl::Fischer { time: seconds(time)?, increment: seconds(increment)? }
+        } else if let Some((time, delay)) = s.split_once('d') {
+            TimeControl::Bronstein { time: seconds(time)?, delay: seconds(delay)? }
+        } else if let Some((moves, time)) = s.split_once('/') {
+            let moves = number(moves)? as u32;
+            if moves == 0 {
+                return Err(format!("bad time control '{}'", s));
+            }
+            TimeControl::MovesPerPeriod { time: seconds(time)?, moves }
+        } else {
+            TimeControl::SuddenDeath { time: seconds(s)? }
+        };
+
+        if control.get_time().is_zero() {
+            return Err(format!("bad time control '{}'", s));
+        }
+        Ok(control)
+    }
+}
+
+/// What a side has left on its clock, for the brain to budget its thinking time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimeLeft {
+    pub remaining: Duration,
+    /// Time given back after each move.
+    pub increment: Duration,
+    /// Moves to play before more time is added, if the control has periods.
+    pub moves_to_go: Option<u32>,
+}
+
+impl TimeLeft {
+    /// Time to spend on the next move: an even share of what is left plus the increment,
+    /// never more than half the remaining time.
+    pub fn get_budget(&self) -> Duration {
+        let moves = self.moves_to_go.unwrap_or(MOVES_TO_GO).max(1);
+        (self.remaining / moves + self.increment).min(self.remaining / 2)
+    }
+}
+
+/// The two clocks of a game, only one of them running at a time.
+#[derive(Clone, Debug)]
+pub struct Clock {
+    control: TimeControl,
+    remaining: [Duration; 2],
+    /// Moves completed by each side.
+    moves: [u32; 2],
+    running: Option<(Turn, Instant)>,
+    flagged: Option<Turn>,
+}
+
+fn index(turn: Turn) -> usize {
+    match turn {
+        Turn::Red => 0,
+        Turn::Black => 1,
+    }
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Clock {
+        let time = control.get_time();
+        Clock { control, remaining: [time, time], moves: [0, 0], running: None, flagged: None }
+    }
+
+    pub fn get_control(&self) -> TimeControl { self.control }
+
+    /// Starts the clock of `turn`, stopping the other one without charging it.
+    pub fn start(&mut self, turn: Turn) {
+        self.running = Some((turn, Instant::now()));
+    }
+
+    /// Stops the running clock, charging the time of the move in progress.
+    pub fn stop(&mut self) {
+        if let Some((turn, started)) = self.running.take() {
+            let i = index(turn);
+            self.remaining[i] = self.remaining[i].saturating_sub(started.elapsed());
+        }
+    }
+
+    /// Ends the move of the running side and starts the other clock. Returns false when the
+    /// flag fell before the move was made.
+    pub fn press(&mut self) -> bool {
+        let Some((turn, started)) = self.running.take() else {
+            return true;
+        };
+
+        let in_time = self.end_move(turn, started.elapsed());
+        if in_time {
+            self.start(turn.opposite());
+        }
+        in_time
+    }
+
+    /// Charges a move of `elapsed` to `turn` and adds the time the control gives back.
+    /// Returns false, flagging `turn`, when it had less time than that.
+    pub fn end_move(&mut self, turn: Turn, elapsed: Duration) -> bool {
+        let i = index(turn);
+        if elapsed > self.remaining[i] {
+            self.remaining[i] = Duration::ZERO;
+            self.flagged = Some(turn);
+            return false;
+        }
+
+        self.remaining[i] -= elapsed;
+        self.moves[i] += 1;
+        match self.control {
+            TimeControl::SuddenDeath { .. } => {}
+            TimeControl::Fischer { increment, .. } => self.remaining[i] += increment,
+            TimeControl::Bronstein { delay, .. } => self.remaining[i] += elapsed.min(delay),
+            TimeControl::MovesPerPeriod { time, moves } => {
+                if self.moves[i].is_multiple_of(moves) {
+                    self.remaining[i] += time;
+                }
+            }
+        }
+        true
+    }
+
+    /// Time left to `turn`, counting the move in progress.
+    pub fn get_remaining(&self, turn: Turn) -> Duration {
+        let remaining = self.remaining[index(turn)];
+        match self.running {
+            Some((running, started)) if running == turn => remaining.saturating_sub(started.elapsed()),
+            _ => remaining,
+        }
+    }
+
+    /// The side that ran out of time, if any.
+    pub fn get_flagged(&self) -> Option<Turn> {
+        self.flagged.or_else(|| {
+            self.running
+                .map(|(turn, _)| turn)
+                .filter(|&turn| self.get_remaining(turn).is_zero())
+        })
+    }
+
+    pub fn get_time_left(&self, turn: Turn) -> TimeLeft {
+        let (increment, moves_to_go) = match self.control {
+            TimeControl::SuddenDeath { .. } => (Duration::ZERO, None),
+            TimeControl::Fischer { increment, .. } => (increment, None),
+            TimeControl::Bronstein { delay, .. } => (delay, None),
+            TimeControl::MovesPerPeriod { moves, .. } => (Duration::ZERO, Some(moves - self.moves[index(turn)] % moves)),
+        };
+        TimeLeft { remaining: self.get_remaining(turn), increment, moves_to_go }
+    }
+}
+
+/// `duration` as minutes and seconds, with tenths under a minute: `4:05`, `0:09.3`.
+pub fn format_time(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds < 60 {
+        format!("0:{:02}.{}", seconds, duration.subsec_millis() / 100)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn time_controls_round_trip() {
+        for text in ["300", "300+5", "300d5", "40/5400"] {
+            assert_eq!(text.parse::<TimeControl>().unwrap().to_string(), text);
+        }
+        assert_eq!("300+5".parse(), Ok(TimeControl::Fischer { time: seconds(300), increment: seconds(5) }));
+
+        for text in ["", "0", "five", "0/300", "300+x"] {
+            assert!(text.parse::<TimeControl>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn moves_give_back_time_by_control() {
+        let mut clock = Clock::new("60+5".parse().unwrap());
+        assert!(clock.end_move(Turn::Red, seconds(10)));
+        assert_eq!(clock.get_remaining(Turn::Red), seconds(55));
+
+        let mut clock = Clock::new("60d5".parse().unwrap());
+        assert!(clock.end_move(Turn::Red, seconds(3)));
+        assert!(clock.end_move(Turn::Red, seconds(10)));
+        assert_eq!(clock.get_remaining(Turn::Red), seconds(55));
+
+        let mut clock = Clock::new("2/60".parse().unwrap());
+        assert!(clock.end_move(Turn::Black, seconds(10)));
+        assert_eq!(clock.get_time_left(Turn::Black).moves_to_go, Some(1));
+        assert!(clock.end_move(Turn::Black, seconds(10)));
+        assert_eq!(clock.get_remaining(Turn::Black), seconds(100));
+        assert_eq!(clock.get_remaining(Turn::Red), seconds(60));
+    }
+
+    #[test]
+    fn running_out_of_time_flags() {
+        let mut clock = Clock::new("60".parse().unwrap());
+        assert!(clock.end_move(Turn::Red, seconds(60)));
+        assert_eq!(clock.get_flagged(), None);
+        assert!(!clock.end_move(Turn::Black, seconds(61)));
+        assert_eq!(clock.get_flagged(), Some(Turn::Black));
+        assert_eq!(clock.get_remaining(Turn::Black), Duration::ZERO);
+    }
+
+    #[test]
+    fn budget_keeps_time_in_reserve() {
+        let time_left = TimeLeft { remaining: seconds(300), increment: seconds(5), moves_to_go: None };
+        assert_eq!(time_left.get_budget(), seconds(15));
+
+        let last_move = TimeLeft { remaining: seconds(10), increment: Duration::ZERO, moves_to_go: Some(1) };
+        assert_eq!(last_move.get_budget(), seconds(5));
+    }
+}
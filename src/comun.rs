@@ -1,27 +1,36 @@
-use bevy::prelude::Component;
+use std::fmt;
 
-pub const BOARD_SIZE: usize = 8;
 pub const WHITE_PAWN: i8 = 1;
 pub const BLACK_PAWN: i8 = -1;
 pub const WHITE_QUEEN: i8 = 2;
 pub const BLACK_QUEEN: i8 = -2;
 pub const EMPTY: i8 = 0;
 
-#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Square {
     pub row: usize,
     pub col: usize,
 }
 
-#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash)]
+/// A whole turn: a simple step or a complete capture chain.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Move {
     pub turn: Turn,
     pub from: Square,
     pub to: Square,
-    pub eat: Option<(Square, i8)>,
+    /// Every landing square in order, the last one being `to`.
+    pub path: Vec<Square>,
+    /// Captured pieces with their value, in the order they are jumped.
+    pub eat: Vec<(Square, i8)>,
     pub promotion: bool
 }
 
+impl Move {
+    pub fn is_capture(&self) -> bool {
+        !self.eat.is_empty()
+    }
+}
+
 #[derive(Clone, Debug, Default, Copy, PartialEq, Eq, Hash)]
 pub enum Turn {
     #[default]
@@ -29,28 +38,63 @@ pub enum Turn {
     Black,
 }
 
-#[derive(Clone, Default)]
+impl Turn {
+    pub fn opposite(self) -> Turn {
+        match self {
+            Turn::Red => Turn::Black,
+            Turn::Black => Turn::Red,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub enum GameStatus {
     #[default]
     Playing,
     RedWins,
     BlackWins,
-    Draw,
+    Draw(DrawReason),
+    /// The side ran out of time and lost.
+    LostOnTime(Turn),
 }
 
-impl PartialEq for GameStatus {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (GameStatus::Playing, GameStatus::Playing) => true,
-            (GameStatus::RedWins, GameStatus::RedWins) => true,
-            (GameStatus::BlackWins, GameStatus::BlackWins) => true,
-            (GameStatus::Draw, GameStatus::Draw) => true,
-            _ => false
+impl GameStatus {
+    /// The side that won the finished game, `None` for a draw or a game in progress.
+    pub fn get_winner(&self) -> Option<Turn> {
+        match self {
+            GameStatus::RedWins => Some(Turn::Red),
+            GameStatus::BlackWins => Some(Turn::Black),
+            GameStatus::LostOnTime(turn) => Some(turn.opposite()),
+            GameStatus::Playing | GameStatus::Draw(_) => None,
         }
     }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawReason {
+    /// The same position with the same side to move appeared three times.
+    Repetition,
+    /// Too many moves in a row without a capture or a pawn move.
+    NoProgress,
+    /// The side with more queens could not beat a lone queen in time.
+    QueenEndgame,
+    /// Both players agreed, or the opponent engine declared the draw.
+    Agreement,
+}
+
+impl fmt::Display for DrawReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            DrawReason::Repetition => "threefold repetition",
+            DrawReason::NoProgress => "no progress",
+            DrawReason::QueenEndgame => "queen endgame",
+            DrawReason::Agreement => "agreement",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub enum GamePlayer {
     #[default]
     Human,
@@ -59,15 +103,13 @@ pub enum GamePlayer {
 
 impl PartialEq for GamePlayer {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (GamePlayer::Human, GamePlayer::Human) => true,
-            (GamePlayer::Computer, GamePlayer::Computer) => true,
-            _ => false
-        }
+        matches!((self, other),
+            (GamePlayer::Human, GamePlayer::Human) |
+            (GamePlayer::Computer, GamePlayer::Computer))
     }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct BandPlayer {
     pub red: GamePlayer,
     pub black: GamePlayer,
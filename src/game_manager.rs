@@ -1,32 +1,116 @@
+use std::fmt;
+use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
-use crate::comun::{BLACK_PAWN, BOARD_SIZE, EMPTY, WHITE_PAWN, WHITE_QUEEN, BLACK_QUEEN,
-                   Move, Turn, Square, GameStatus, BandPlayer, GamePlayer};
-use crate::ia::brain::Brain;
-use crate::ia::monte_carlo_impl::MonteCarlo;
-
-const INITIAL_BOARD: [[i8; BOARD_SIZE]; BOARD_SIZE] = [
-    [WHITE_PAWN, EMPTY, WHITE_PAWN, EMPTY, WHITE_PAWN, EMPTY, WHITE_PAWN, EMPTY],
-    [EMPTY, WHITE_PAWN, EMPTY, WHITE_PAWN, EMPTY, WHITE_PAWN, EMPTY, WHITE_PAWN],
-    [WHITE_PAWN, EMPTY, WHITE_PAWN, EMPTY, WHITE_PAWN, EMPTY, WHITE_PAWN, EMPTY],
-    [EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY],
-    [EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY],
-    [EMPTY, BLACK_PAWN, EMPTY, BLACK_PAWN, EMPTY, BLACK_PAWN, EMPTY, BLACK_PAWN],
-    [BLACK_PAWN, EMPTY, BLACK_PAWN, EMPTY, BLACK_PAWN, EMPTY, BLACK_PAWN, EMPTY],
-    [EMPTY, BLACK_PAWN, EMPTY, BLACK_PAWN, EMPTY, BLACK_PAWN, EMPTY, BLACK_PAWN],
-];
+use crate::bitboard::{Bitboards, BITBOARD_SIZE};
+use crate::clock::Clock;
+use crate::comun::{BLACK_PAWN, EMPTY, WHITE_PAWN, WHITE_QUEEN, BLACK_QUEEN,
+                   Move, Turn, Square, GameStatus, DrawReason, BandPlayer, GamePlayer};
+use crate::ia::brain::{Brain, SearchInfo, SearchLimits};
+use crate::ia::mcts::Mcts;
+use crate::notation::{self, Fen, FenError, MoveText};
+use crate::rules::{CapturePriority, PromotionDuringCapture, RuleSet, Variant};
+use crate::zobrist;
+
+const DIAGONALS: [(isize, isize); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+/// Why `try_move` refused a move.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MoveError {
+    /// The game already ended with this status.
+    GameOver(GameStatus),
+    /// The move, or the piece it starts from, belongs to the side not on move.
+    WrongTurn,
+    /// A simple move while a capture is available.
+    CaptureMandatory,
+    /// The start of a legal capture that stops before the chain is over.
+    MustContinueChain,
+    /// Any other move that is not among the legal ones, or one that fits several of them.
+    NotLegal,
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::GameOver(status) => write!(f, "the game is over ({:?})", status),
+            MoveError::WrongTurn => write!(f, "it is not that side's turn"),
+            MoveError::CaptureMandatory => write!(f, "a capture is mandatory"),
+            MoveError::MustContinueChain => write!(f, "the capture has to continue"),
+            MoveError::NotLegal => write!(f, "not a legal move"),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+/// Everything `do_move` changes besides the board, saved so `undo_move` restores it exactly.
+#[derive(Clone, Debug, PartialEq)]
+struct Snapshot {
+    played: Move,
+    turn: Turn,
+    game_status: GameStatus,
+    moves_without_progress: u16,
+    queen_endgame_moves: u16,
+}
 
 #[derive(Clone)]
 pub struct GameManager{
-    board: [[i8; BOARD_SIZE]; BOARD_SIZE],
+    rules: RuleSet,
+    board: Vec<Vec<i8>>,
+    /// Same position as `board`, kept for the 8x8 variants to generate moves faster.
+    bitboards: Option<Bitboards>,
+    /// Zobrist hash of the position and the side to move, updated with every change.
+    hash: u64,
     moves: Vec<Move>,
-    moves_with_no_capture: i8,
-    moves_with_no_capture_history: Vec<i8>,
+    /// Whether `moves` holds the legal moves of the current position.
+    moves_ready: bool,
+    moves_without_progress: u16,
+    queen_endgame_moves: u16,
+    history: Vec<Snapshot>,
+    /// Hash of every position reached, the last one being the current position.
+    position_history: Vec<u64>,
     turn: Turn,
     band_player: BandPlayer,
     game_status: GameStatus,
+    /// Time left to each side, in a timed game.
+    clock: Option<Clock>,
     brain: Arc<Mutex<dyn Brain>>
 }
 
+/// Two games are equal when they have the same position, state and history.
+/// The bitboards mirror the board; the legal moves cache, the clock and the brain are not part of the game.
+impl PartialEq for GameManager {
+    fn eq(&self, other: &Self) -> bool {
+        self.rules == other.rules
+            && self.board == other.board
+            && self.hash == other.hash
+            && self.moves_without_progress == other.moves_without_progress
+            && self.queen_endgame_moves == other.queen_endgame_moves
+            && self.history == other.history
+            && self.position_history == other.position_history
+            && self.turn == other.turn
+            && self.band_player == other.band_player
+            && self.game_status == other.game_status
+    }
+}
+
+impl fmt::Debug for GameManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GameManager")
+            .field("rules", &self.rules)
+            .field("board", &self.board)
+            .field("hash", &self.hash)
+            .field("moves_without_progress", &self.moves_without_progress)
+            .field("queen_endgame_moves", &self.queen_endgame_moves)
+            .field("history", &self.history)
+            .field("position_history", &self.position_history)
+            .field("turn", &self.turn)
+            .field("band_player", &self.band_player)
+            .field("game_status", &self.game_status)
+            .field("clock", &self.clock)
+            .finish_non_exhaustive()
+    }
+}
+
 impl Default for GameManager {
     fn default() -> Self {
         Self::new()
@@ -35,27 +119,139 @@ impl Default for GameManager {
 
 impl GameManager {
     pub fn new() -> GameManager {
-        GameManager {
-            board: INITIAL_BOARD.clone(),
+        Self::with_variant(Variant::default())
+    }
+
+    pub fn with_variant(variant: Variant) -> GameManager {
+        Self::with_rules(variant.rules())
+    }
+
+    pub fn with_rules(rules: RuleSet) -> GameManager {
+        let board = Self::get_initial_board(&rules);
+        let bitboards = (rules.board_size == BITBOARD_SIZE).then(|| Bitboards::from_board(&board));
+
+        let mut game_manager = GameManager {
+            rules,
+            board,
+            bitboards,
+            hash: 0,
             moves: Vec::new(),
-            moves_with_no_capture: 0,
-            moves_with_no_capture_history: Vec::new(),
-            turn: Turn::Red,
+            moves_ready: false,
+            moves_without_progress: 0,
+            queen_endgame_moves: 0,
+            history: Vec::new(),
+            position_history: Vec::new(),
+            turn: rules.first_turn,
             band_player: BandPlayer {
                 red: GamePlayer::Human,
                 black: GamePlayer::Computer,
             },
             game_status: GameStatus::Playing,
-            brain: Arc::new(Mutex::new(MonteCarlo::new()))
+            clock: None,
+            brain: Arc::new(Mutex::new(Mcts::new()))
+        };
+        game_manager.hash = game_manager.compute_hash();
+        game_manager.position_history.push(game_manager.hash);
+        game_manager
+    }
+
+    fn get_initial_board(rules: &RuleSet) -> Vec<Vec<i8>> {
+        let mut board = vec![vec![EMPTY; rules.board_size]; rules.board_size];
+
+        for (row, cells) in board.iter_mut().enumerate() {
+            for (col, cell) in cells.iter_mut().enumerate() {
+                if (row + col) % 2 != 0 {
+                    continue;
+                }
+                if row < rules.pawn_rows {
+                    *cell = WHITE_PAWN;
+                } else if row >= rules.board_size - rules.pawn_rows {
+                    *cell = BLACK_PAWN;
+                }
+            }
         }
+
+        board
     }
 
-    #[allow(dead_code)]
+    /// Sets up a game of `variant` from a PDN FEN position such as `W:W21,22,K5:B1,2,3`.
+    /// The game starts there with no history.
+    pub fn from_fen(variant: Variant, fen: &str) -> Result<GameManager, FenError> {
+        let fen: Fen = fen.parse()?;
+        let mut game_manager = GameManager::with_variant(variant);
+        let size = game_manager.rules.board_size;
+        let max_pieces = game_manager.rules.pawn_rows * size / 2;
+
+        if fen.red.len() > max_pieces {
+            return Err(FenError::TooManyPieces(Turn::Red));
+        }
+        if fen.black.len() > max_pieces {
+            return Err(FenError::TooManyPieces(Turn::Black));
+        }
+
+        for row in 0..size {
+            for col in 0..size {
+                game_manager.set_piece(Square { row, col }, EMPTY);
+            }
+        }
+
+        let red = fen.red.iter().map(|&(number, queen)| (number, if queen { WHITE_QUEEN } else { WHITE_PAWN }));
+        let black = fen.black.iter().map(|&(number, queen)| (number, if queen { BLACK_QUEEN } else { BLACK_PAWN }));
+        for (number, piece) in red.chain(black) {
+            let square = notation::number_to_square(number, size).ok_or(FenError::SquareOutOfRange(number))?;
+            if game_manager.board[square.row][square.col] != EMPTY {
+                return Err(FenError::DuplicateSquare(number));
+            }
+            let last_row = if piece == WHITE_PAWN { size - 1 } else { 0 };
+            if !game_manager.is_queen(piece) && square.row == last_row {
+                return Err(FenError::PawnOnLastRow(number));
+            }
+            game_manager.set_piece(square, piece);
+        }
+
+        if game_manager.turn != fen.turn {
+            game_manager.switch_turn();
+        }
+        game_manager.position_history = vec![game_manager.hash];
+        game_manager.moves_ready = false;
+        game_manager.game_status = game_manager.calculate_game_status();
+
+        Ok(game_manager)
+    }
+
+    /// The current position in PDN FEN.
+    pub fn to_fen(&self) -> String {
+        let size = self.rules.board_size;
+        let mut fen = Fen { turn: self.turn, ..Fen::default() };
+
+        for (row, cells) in self.board.iter().enumerate() {
+            for (col, &piece) in cells.iter().enumerate() {
+                if piece == EMPTY {
+                    continue;
+                }
+                let entry = (notation::square_to_number(Square { row, col }, size), self.is_queen(piece));
+                if self.is_black_piece(piece) {
+                    fen.black.push(entry);
+                } else {
+                    fen.red.push(entry);
+                }
+            }
+        }
+
+        fen.to_string()
+    }
+
+    pub fn get_rules(&self) -> &RuleSet { &self.rules }
+
     pub fn print_board(&self) {
-        println!("  0 1 2 3 4 5 6 7");
-        println!("  ---------------");
+        let size = self.rules.board_size;
+        let columns: String = (0..size).map(|col| format!("{:<2}", col)).collect();
+        let separator = "-".repeat(size * 2 - 1);
+
+        println!("   {}", columns);
+        println!("   {}", separator);
         for (i, row) in self.board.iter().enumerate().rev() {
-            print!("{} ", 8 - i - 1);
+            print!("{:>2} ", size - i - 1);
             for &cell in row {
                 let symbol = match cell {
                     WHITE_PAWN => "●",
@@ -67,46 +263,97 @@ impl GameManager {
                 };
                 print!("{} ", symbol);
             }
-            println!("| {}", 8 - i);
+            // numbers of the playable squares of the row, as used in move notation
+            let first = (size - 1 - i) * (size / 2) + 1;
+            println!("| {}-{}", first, first + size / 2 - 1);
         }
-        println!("  ---------------");
-        println!("  0 1 2 3 4 5 6 7");
+        println!("   {}", separator);
+        println!("   {}", columns);
     }
 
     pub fn get_band_player(&self) -> &BandPlayer { &self.band_player }
 
-    pub fn get_board(&self) -> &[[i8; BOARD_SIZE]; BOARD_SIZE] {
+    pub fn set_band_player(&mut self, band_player: BandPlayer) { self.band_player = band_player; }
+
+    /// Replaces the AI answering `computer_plays`.
+    pub fn set_brain(&mut self, brain: impl Brain + 'static) {
+        self.brain = Arc::new(Mutex::new(brain));
+    }
+
+    /// Uses the same AI as `other`, for a new game set up from it.
+    pub fn share_brain(&mut self, other: &GameManager) {
+        self.brain = other.brain.clone();
+    }
+
+    /// Plays the rest of the game on `clock`, starting the clock of the side to move.
+    pub fn set_clock(&mut self, mut clock: Clock) {
+        clock.start(self.turn);
+        self.clock = Some(clock);
+    }
+
+    pub fn get_clock(&self) -> Option<&Clock> { self.clock.as_ref() }
+
+    /// Ends the game if the side to move has run out of time, and returns its status.
+    pub fn check_clock(&mut self) -> GameStatus {
+        let flagged = self.clock.as_ref().and_then(Clock::get_flagged);
+        if self.game_status == GameStatus::Playing && flagged == Some(self.turn) {
+            self.game_status = GameStatus::LostOnTime(self.turn);
+            if let Some(clock) = &mut self.clock {
+                clock.stop();
+            }
+        }
+        self.game_status.clone()
+    }
+
+    pub fn get_board(&self) -> &[Vec<i8>] {
         &self.board
     }
 
+    pub fn get_board_size(&self) -> usize { self.rules.board_size }
+
     pub fn get_legal_moves(&mut self) -> &Vec<Move> {
+        if self.moves_ready {
+            return &self.moves;
+        }
+        self.moves_ready = true;
         self.moves.clear();
-        let mut raw_moves: Vec<Move> = Vec::new();
 
-        for row in 0..BOARD_SIZE {
-            for col in 0..BOARD_SIZE {
-                let l_moves = match self.turn {
-                    Turn::Red => self.get_legal_moves_for_piece(row, col),
-                    Turn::Black => self.get_legal_moves_for_piece(row, col)
-                };
-
-                raw_moves.extend(l_moves.clone());
+        let raw_moves: Vec<Move> = match self.bitboards {
+            Some(bitboards) if !bitboards.has_captures(self.turn, &self.rules) => {
+                self.moves = bitboards.get_quiet_moves(self.turn, &self.rules);
+                return &self.moves;
             }
-        }
+            Some(bitboards) => bitboards.get_captures(self.turn, &self.rules),
+            None => {
+                let mut raw_moves = Vec::new();
+                for row in 0..self.rules.board_size {
+                    for col in 0..self.rules.board_size {
+                        raw_moves.extend(self.get_legal_moves_for_piece(row, col));
+                    }
+                }
+                raw_moves
+            }
+        };
 
         // if there is a eat move, delete all non eat moves
-        let mut has_eat = false;
-        for m in raw_moves.iter() {
-            if m.eat.is_some() {
-                has_eat = true;
-                break;
-            }
-        }
+        let has_eat = raw_moves.iter().any(|m| m.is_capture());
 
         if has_eat {
-            for m in raw_moves.iter() {
-                if m.eat.is_some() {
-                    self.moves.push(m.clone());
+            // only the best capture sequences under the variant priority are legal
+            let scored: Vec<(Move, Vec<usize>)> = raw_moves.into_iter()
+                .filter(|m| m.is_capture())
+                .map(|m| {
+                    let score = self.get_capture_score(&m);
+                    (m, score)
+                })
+                .collect();
+
+            if let Some(best) = scored.iter().map(|(_, score)| score.clone()).max() {
+                for (m, score) in scored {
+                    // a queen may take the same pieces through different paths, which is the same move
+                    if score == best && !self.moves.iter().any(|other| Self::is_same_capture(other, &m)) {
+                        self.moves.push(m);
+                    }
                 }
             }
         } else {
@@ -116,13 +363,34 @@ impl GameManager {
         &self.moves
     }
 
-    pub fn calculate_game_status(&mut self) -> GameStatus {
-        if self.moves_with_no_capture >= 16 {
-            return GameStatus::Draw;
+    fn is_same_capture(a: &Move, b: &Move) -> bool {
+        a.from == b.from && a.to == b.to && a.eat.len() == b.eat.len()
+            && a.eat.iter().all(|eaten| b.eat.contains(eaten))
+    }
+
+    /// Ranks a capture under the variant capture priority; only the highest scores are legal.
+    /// Scores are compared lexicographically, so their layout follows the priority order.
+    fn get_capture_score(&self, m: &Move) -> Vec<usize> {
+        let pieces = m.eat.len();
+        let queens = m.eat.iter().filter(|(_, piece)| self.is_queen(*piece)).count();
+        let with_queen = usize::from(self.is_queen(self.board[m.from.row][m.from.col]));
+
+        match self.rules.capture_priority {
+            CapturePriority::Free => Vec::new(),
+            CapturePriority::Quantity => vec![pieces],
+            CapturePriority::QuantityAndQuality => vec![pieces, queens, with_queen],
+            CapturePriority::Italian => {
+                // the sooner a queen is captured the better
+                let mut score = vec![pieces, with_queen, queens];
+                score.extend(m.eat.iter().map(|(_, piece)| usize::from(self.is_queen(*piece))));
+                score
+            }
         }
+    }
 
+    pub fn calculate_game_status(&mut self) -> GameStatus {
         let moves = self.get_legal_moves();
-        if moves.len() == 0 {
+        if moves.is_empty() {
             if self.turn == Turn::Red {
                 return GameStatus::BlackWins;
             } else {
@@ -130,244 +398,532 @@ impl GameManager {
             }
         }
 
-        let mut white_pieces = 0;
-        let mut black_pieces = 0;
+        if self.is_repetition() {
+            return GameStatus::Draw(DrawReason::Repetition);
+        }
 
-        for row in 0..BOARD_SIZE {
-            for col in 0..BOARD_SIZE {
-                if self.is_white_piece(self.board[row][col]) {
-                    white_pieces += 1;
-                } else if self.is_black_piece(self.board[row][col]) {
-                    black_pieces += 1;
-                }
-            }
+        if self.rules.no_progress_limit.is_some_and(|limit| self.moves_without_progress >= limit) {
+            return GameStatus::Draw(DrawReason::NoProgress);
         }
 
-        if white_pieces == 0 {
-            return GameStatus::BlackWins
-        } else if black_pieces == 0 {
-            return GameStatus::RedWins
+        if self.rules.queen_endgame_limit.is_some_and(|limit| self.queen_endgame_moves >= limit) {
+            return GameStatus::Draw(DrawReason::QueenEndgame);
         }
 
         GameStatus::Playing
     }
 
-    pub fn do_move(&mut self, m: Move) {
+    /// Zobrist hash of the current position, including the side to move.
+    /// Equal positions always share the hash, whatever the moves that led to them.
+    pub fn get_hash(&self) -> u64 { self.hash }
+
+    /// Computes the hash from scratch; `hash` is kept equal to it incrementally.
+    fn compute_hash(&self) -> u64 {
+        let mut hash = 0;
+
+        for (row, cells) in self.board.iter().enumerate() {
+            for (col, &piece) in cells.iter().enumerate() {
+                hash ^= zobrist::piece_key(Square { row, col }, piece);
+            }
+        }
+        if self.turn == Turn::Black {
+            hash ^= zobrist::side_key();
+        }
+
+        hash
+    }
+
+    fn is_repetition(&self) -> bool {
+        let Some(current) = self.position_history.last() else {
+            return false;
+        };
+
+        // positions before the last capture or pawn move can never come back
+        self.position_history.iter()
+            .rev()
+            .take(self.moves_without_progress as usize + 1)
+            .filter(|&hash| hash == current)
+            .count() >= self.rules.repetition_limit
+    }
+
+    /// True when one side only has a lone queen and the other side only has the
+    /// `queen_endgame_queens` queens of the rules.
+    fn is_queen_endgame(&self) -> bool {
+        if let Some(bitboards) = self.bitboards {
+            let (red_pawns, red_queens, black_pawns, black_queens) = bitboards.count();
+            return red_pawns == 0 && black_pawns == 0 && red_queens.min(black_queens) == 1
+                && red_queens.max(black_queens) as usize == self.rules.queen_endgame_queens;
+        }
+
+        let mut pieces = [0; 2];
+        let mut queens = [0; 2];
+
+        for &piece in self.board.iter().flatten() {
+            if piece == EMPTY {
+                continue;
+            }
+            let side = usize::from(self.is_black_piece(piece));
+            pieces[side] += 1;
+            if self.is_queen(piece) {
+                queens[side] += 1;
+            }
+        }
+
+        pieces == queens && pieces.iter().min() == Some(&1) && pieces.iter().max() == Some(&self.rules.queen_endgame_queens)
+    }
+
+    pub fn do_move(&mut self, m: &Move) {
         let from = m.from;
         let to = m.to;
-        let eat = m.eat;
 
-        self.board[to.row][to.col] = self.board[from.row][from.col];
-        self.board[from.row][from.col] = EMPTY;
+        self.history.push(Snapshot {
+            played: m.clone(),
+            turn: self.turn,
+            game_status: self.game_status.clone(),
+            moves_without_progress: self.moves_without_progress,
+            queen_endgame_moves: self.queen_endgame_moves,
+        });
 
-        self.moves_with_no_capture_history.push(self.moves_with_no_capture);
+        self.moves_ready = false;
 
-        let mut can_eat = false;
-        if let Some(eat) = eat {
-            self.board[eat.0.row][eat.0.col] = EMPTY;
-            self.moves_with_no_capture = 0;
+        let piece = self.board[from.row][from.col];
+        self.set_piece(from, EMPTY);
+        self.set_piece(to, piece);
 
-            // check if you can eat again
-            let moves = self.get_legal_moves_for_piece(to.row, to.col);
-            for m in moves.iter() {
-                if m.eat.is_some() {
-                    can_eat = true;
-                    break;
-                }
-            }
+        for (square, _) in m.eat.iter() {
+            self.set_piece(*square, EMPTY);
+        }
+
+        if m.is_capture() || !self.is_queen(piece) {
+            self.moves_without_progress = 0;
         }
         else {
-            self.moves_with_no_capture += 1;
+            self.moves_without_progress += 1;
         }
 
-        if m.promotion && self.board[to.row][to.col] == WHITE_PAWN {
-            self.board[to.row][to.col] = WHITE_QUEEN;
-        }
-        else if m.promotion && self.board[to.row][to.col] == BLACK_PAWN {
-            self.board[to.row][to.col] = BLACK_QUEEN;
+        if m.promotion {
+            self.set_piece(to, self.crown(piece));
         }
 
-        if !can_eat {
-            self.switch_turn();
+        self.switch_turn();
+
+        if self.is_queen_endgame() {
+            self.queen_endgame_moves += 1;
+        }
+        else {
+            self.queen_endgame_moves = 0;
         }
+        self.position_history.push(self.hash);
 
         self.game_status = self.calculate_game_status();
     }
 
-    pub fn undo_move(&mut self, m: Move) {
+    /// Plays `m` only if it is legal in the current position. The move is matched against the
+    /// legal moves by its squares: `path` may be left empty when `from` and `to` are enough
+    /// to tell it apart, and the captured pieces and promotion are always taken from the game.
+    /// On a clock, the move also presses it, and a side out of time loses instead of moving.
+    pub fn try_move(&mut self, m: &Move) -> Result<(), MoveError> {
+        if self.check_clock() != GameStatus::Playing {
+            return Err(MoveError::GameOver(self.game_status.clone()));
+        }
+
+        let piece = self.board.get(m.from.row).and_then(|row| row.get(m.from.col)).copied().unwrap_or(EMPTY);
+        let own_piece = piece != EMPTY && self.is_black_piece(piece) == (self.turn == Turn::Black);
+        if m.turn != self.turn || (piece != EMPTY && !own_piece) {
+            return Err(MoveError::WrongTurn);
+        }
+
+        let path = if m.path.is_empty() { vec![m.to] } else { m.path.clone() };
+        let legal_moves = self.get_legal_moves();
+        let mut matching = legal_moves.iter()
+            .filter(|legal| legal.from == m.from && legal.to == m.to && (m.path.is_empty() || legal.path == m.path));
+
+        if let (Some(legal), None) = (matching.next(), matching.next()) {
+            let legal = legal.clone();
+            self.do_move(&legal);
+            self.press_clock(legal.turn);
+            return Ok(());
+        }
+
+        let captures_available = legal_moves.iter().any(|legal| legal.is_capture());
+        let is_chain_start = legal_moves.iter()
+            .any(|legal| legal.from == m.from && legal.path.len() > path.len() && legal.path.starts_with(&path));
+
+        if is_chain_start {
+            Err(MoveError::MustContinueChain)
+        } else if captures_available && !m.is_capture() && self.is_slide(m.from, path[0]) {
+            Err(MoveError::CaptureMandatory)
+        } else {
+            Err(MoveError::NotLegal)
+        }
+    }
+
+    /// Ends the move of `turn` on the clock, stopping it once the game is over.
+    fn press_clock(&mut self, turn: Turn) {
+        let Some(clock) = &mut self.clock else {
+            return;
+        };
+
+        if !clock.press() {
+            self.game_status = GameStatus::LostOnTime(turn);
+        }
+        if self.game_status != GameStatus::Playing {
+            clock.stop();
+        }
+    }
+
+    /// Whether `from` and `to` are on the same diagonal with nothing between them.
+    fn is_slide(&self, from: Square, to: Square) -> bool {
+        let (d_row, d_col) = (to.row as isize - from.row as isize, to.col as isize - from.col as isize);
+        if d_row == 0 || d_row.abs() != d_col.abs() {
+            return false;
+        }
+
+        (1..d_row.abs())
+            .filter_map(|step| self.offset(from, d_row.signum() * step, d_col.signum() * step))
+            .all(|square| self.board[square.row][square.col] == EMPTY)
+    }
+
+    /// Takes back the last move played, restoring the exact state before it.
+    /// Returns the move undone, or `None` if no move has been played.
+    pub fn undo_move(&mut self) -> Option<Move> {
+        let snapshot = self.history.pop()?;
+        let m = snapshot.played;
         let from = m.from;
         let to = m.to;
-        let eat = m.eat;
 
-        self.board[from.row][from.col] = self.board[to.row][to.col];
-        self.board[to.row][to.col] = EMPTY;
+        self.moves_ready = false;
 
-        if let Some(eat) = eat {
-            self.board[eat.0.row][eat.0.col] = eat.1;
-        }
+        let piece = self.board[to.row][to.col];
+        self.set_piece(to, EMPTY);
+        self.set_piece(from, if m.promotion { self.uncrown(piece) } else { piece });
 
-        if m.promotion {
-            if self.board[from.row][from.col] == WHITE_QUEEN {
-                self.board[from.row][from.col] = WHITE_PAWN;
-            }
-            else if self.board[from.row][from.col] == BLACK_QUEEN {
-                self.board[from.row][from.col] = BLACK_PAWN;
-            }
+        for (square, eaten) in m.eat.iter() {
+            self.set_piece(*square, *eaten);
         }
 
-        self.moves_with_no_capture = self.moves_with_no_capture_history.pop().unwrap();
+        if self.turn != snapshot.turn {
+            self.switch_turn();
+        }
+        self.game_status = snapshot.game_status;
+        self.moves_without_progress = snapshot.moves_without_progress;
+        self.queen_endgame_moves = snapshot.queen_endgame_moves;
+        self.position_history.pop();
 
-        self.game_status = self.calculate_game_status();
+        Some(m)
     }
 
     pub fn get_turn(&self) -> Turn { self.turn }
 
+    /// Moves played since the game started, in order.
+    pub fn get_played_moves(&self) -> Vec<Move> {
+        self.history.iter().map(|snapshot| snapshot.played.clone()).collect()
+    }
+
     pub fn get_game_status(&self) -> GameStatus { self.game_status.clone() }
 
-    pub fn computer_plays(&mut self) -> Move {
+    pub fn computer_plays(&mut self) -> Option<Move> {
         let mut brain = self.brain.lock().unwrap();
-        brain.get_best_move(self.clone())
+        match &self.clock {
+            // on a clock the brain budgets its time from what is left
+            Some(clock) => {
+                let limits = SearchLimits { clock: Some(clock.get_time_left(self.turn)), ..SearchLimits::default() };
+                brain.search(self.clone(), &limits, &AtomicBool::new(false), &mut |_| {})
+            }
+            None => brain.get_best_move(self.clone()),
+        }
+    }
+
+    /// `computer_plays` within `limits`, stopping early once `stop` is set.
+    pub fn computer_search(&mut self, limits: &SearchLimits, stop: &AtomicBool, info: &mut dyn FnMut(SearchInfo)) -> Option<Move> {
+        let mut brain = self.brain.lock().unwrap();
+        brain.search(self.clone(), limits, stop, info)
+    }
+
+    /// `m` in numeric notation, with its whole path only when another legal move
+    /// joins the same squares. `m` must be legal in the current position.
+    pub fn get_move_text(&mut self, m: &Move) -> String {
+        let size = self.rules.board_size;
+        let ambiguous = self.get_legal_moves().iter()
+            .filter(|other| other.from == m.from && other.to == m.to)
+            .count() > 1;
+
+        MoveText::from_move(m, size, ambiguous).to_string()
+    }
+
+    /// The legal move written as `text`, such as `11-15`, `22x6` or `22x13x6`.
+    /// Returns `None` when no legal move matches or when the text fits several of them.
+    pub fn find_move(&mut self, text: &str) -> Option<Move> {
+        let text: MoveText = text.parse().ok()?;
+        let size = self.rules.board_size;
+        let mut candidates = self.get_legal_moves().iter().filter(|m| text.matches(m, size));
+
+        match (candidates.next(), candidates.next()) {
+            (Some(m), None) => Some(m.clone()),
+            _ => None,
+        }
+    }
+
+    /// Counts the leaves of the legal move tree `depth` plies deep. Moves are generated
+    /// even when the game is already drawn, so the counts only depend on the move rules.
+    pub fn perft(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        let moves = self.get_legal_moves().clone();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+
+        let mut nodes = 0;
+        for m in moves.iter() {
+            self.do_move(m);
+            nodes += self.perft(depth - 1);
+            self.undo_move();
+        }
+
+        nodes
+    }
+
+    /// Perft split by root move: the leaves `depth` plies deep under each legal move.
+    /// Depths 0 and 1 both count one leaf per move.
+    pub fn perft_divide(&mut self, depth: u32) -> Vec<(Move, u64)> {
+        let moves = self.get_legal_moves().clone();
+        let mut divide = Vec::new();
+
+        for m in moves {
+            self.do_move(&m);
+            let nodes = self.perft(depth.saturating_sub(1));
+            self.undo_move();
+            divide.push((m, nodes));
+        }
+
+        divide
     }
 
     fn get_legal_moves_for_piece(&self, row: usize, col: usize) -> Vec<Move> {
         let piece = self.board[row][col];
-        let mut moves = Vec::new();
-        let cell_from = Square { row, col };
 
         match piece {
-            WHITE_PAWN => {
-                if self.turn == Turn::Red {
-                    if row < BOARD_SIZE - 1 && col > 0 && self.board[row + 1][col - 1] == EMPTY {
-                        let promotion = row + 1 == BOARD_SIZE - 1;
-                        moves.push(Move { from: cell_from.clone(), to: Square{ row: row + 1, col: col - 1 }, turn: self.turn, eat: None, promotion });
-                    }
-                    if row < BOARD_SIZE - 1 && col < BOARD_SIZE - 1 && self.board[row + 1][col + 1] == EMPTY {
-                        let promotion = row + 1 == BOARD_SIZE - 1;
-                        moves.push(Move { from: cell_from.clone(), to: Square{ row: row + 1, col: col + 1 }, turn: self.turn, eat: None, promotion });
-                    }
-                    if row < BOARD_SIZE - 2 && col > 1 && self.is_black_piece(self.board[row + 1][col - 1]) && self.board[row + 2][col - 2] == EMPTY {
-                        let promotion = row + 2 == BOARD_SIZE - 1;
-                        moves.push(Move { from: cell_from.clone(), to: Square{ row: row + 2, col: col - 2 }, turn: self.turn, eat: Some((Square{ row: row + 1, col: col - 1 }, self.get_piece_at(row + 1, col - 1))), promotion });
-                    }
-                    if row < BOARD_SIZE - 2 && col < BOARD_SIZE - 2 && self.is_black_piece(self.board[row + 1][col + 1]) && self.board[row + 2][col + 2] == EMPTY {
-                        let promotion = row + 2 == BOARD_SIZE - 1;
-                        moves.push(Move { from: cell_from.clone(), to: Square{ row: row + 2, col: col + 2 }, turn: self.turn, eat: Some((Square{ row: row + 1, col: col + 1 }, self.get_piece_at(row + 1, col + 1))), promotion });
-                    }
-                }
-            },
-            WHITE_QUEEN => {
-                if self.turn == Turn::Red {
-                    moves.extend(self.get_queen_moves(row, col));
-                }
-            },
-            BLACK_PAWN => {
-                if self.turn == Turn::Black {
-                    if row > 0 && col > 0 && self.board[row - 1][col - 1] == EMPTY {
-                        let promotion = row - 1 == 0;
-                        moves.push(Move { from: cell_from.clone(), to: Square{ row: row - 1, col: col - 1 }, turn: self.turn, eat: None, promotion });
-                    }
-                    if row > 0 && col < BOARD_SIZE - 1 && self.board[row - 1][col + 1] == EMPTY {
-                        let promotion = row - 1 == 0;
-                        moves.push(Move { from: cell_from.clone(), to: Square{ row: row - 1, col: col + 1 }, turn: self.turn, eat: None, promotion });
-                    }
-                    if row > 1 && col > 1 && self.is_white_piece(self.board[row - 1][col - 1]) && self.board[row - 2][col - 2] == EMPTY {
-                        let promotion = row - 2 == 0;
-                        moves.push(Move { from: cell_from.clone(), to: Square{ row: row - 2, col: col - 2 }, turn: self.turn, eat: Some((Square{ row: row - 1, col: col - 1 }, self.get_piece_at(row - 1, col - 1))), promotion });
-                    }
-                    if row > 1 && col < BOARD_SIZE - 2 && self.is_white_piece(self.board[row - 1][col + 1]) && self.board[row - 2][col + 2] == EMPTY {
-                        let promotion = row - 2 == 0;
-                        moves.push(Move { from: cell_from.clone(), to: Square{ row: row - 2, col: col + 2 }, turn: self.turn, eat: Some((Square{ row: row - 1, col: col + 1 }, self.get_piece_at(row - 1, col + 1))), promotion });
-                    }
-                }
-            },
-            BLACK_QUEEN => {
-                if self.turn == Turn::Black {
-                    moves.extend(self.get_queen_moves(row, col));
+            WHITE_PAWN if self.turn == Turn::Red => self.get_pawn_moves(row, col),
+            WHITE_QUEEN if self.turn == Turn::Red => self.get_queen_moves(row, col),
+            BLACK_PAWN if self.turn == Turn::Black => self.get_pawn_moves(row, col),
+            BLACK_QUEEN if self.turn == Turn::Black => self.get_queen_moves(row, col),
+            _ => Vec::new()
+        }
+    }
+
+    fn get_pawn_moves(&self, row: usize, col: usize) -> Vec<Move> {
+        let piece = self.board[row][col];
+        let cell_from = Square { row, col };
+        let mut moves = Vec::new();
+
+        for side in [-1, 1] {
+            if let Some(to) = self.offset(cell_from, self.get_forward(piece), side) {
+                if self.board[to.row][to.col] == EMPTY {
+                    moves.push(Move { from: cell_from, to, turn: self.turn, path: vec![to], eat: Vec::new(), promotion: to.row == self.get_last_row(piece) });
                 }
-            },
-            _ => {}
-        };
+            }
+        }
 
+        moves.extend(self.get_captures(row, col));
         moves
     }
 
     fn get_queen_moves(&self, row: usize, col: usize) -> Vec<Move> {
-        let mut moves = Vec::new();
         let cell_from = Square { row, col };
-        let mut eated_square: Option<(Square, i8)> = None;
+        let mut moves = Vec::new();
 
-        for i in 1..BOARD_SIZE {
-            if row + i < BOARD_SIZE && col + i < BOARD_SIZE {
-                if self.board[row + i][col + i] == EMPTY {
-                    moves.push(Move { from: cell_from.clone(), to: Square{ row: row + i, col: col + i }, turn: self.turn, eat: eated_square.clone(), promotion: false});
-                }
-                else if self.board[row + i][col + i] * self.board[row][col] < 0 && eated_square.is_none() {
-                    eated_square = Some((Square{ row: row + i, col: col + i }, self.get_piece_at(row + i, col + i)));
+        for (d_row, d_col) in DIAGONALS {
+            let mut current = cell_from;
+            while let Some(to) = self.offset(current, d_row, d_col) {
+                if self.board[to.row][to.col] != EMPTY {
+                    break;
                 }
-                else {
+                moves.push(Move { from: cell_from, to, turn: self.turn, path: vec![to], eat: Vec::new(), promotion: false });
+                if !self.rules.flying_queens {
                     break;
                 }
+                current = to;
             }
         }
 
-        eated_square = None;
-        for i in 1..BOARD_SIZE {
-            if row + i < BOARD_SIZE && col >= i {
-                if self.board[row + i][col - i] == EMPTY {
-                    moves.push(Move { from: cell_from.clone(), to: Square{ row: row + i, col: col - i }, turn: self.turn, eat: eated_square.clone(), promotion: false});
-                }
-                else if self.board[row + i][col - i] * self.board[row][col] < 0 && eated_square.is_none() {
-                    eated_square = Some((Square{ row: row + i, col: col - i }, self.get_piece_at(row + i, col - i)));
-                }
-                else {
-                    break;
+        moves.extend(self.get_captures(row, col));
+        moves
+    }
+
+    fn get_captures(&self, row: usize, col: usize) -> Vec<Move> {
+        let piece = self.board[row][col];
+        let cell_from = Square { row, col };
+        let mut moves = Vec::new();
+
+        if self.get_jumps(&self.board, piece, cell_from).is_empty() {
+            return moves;
+        }
+
+        // the capturing piece leaves its square while the chain is explored
+        let mut board = self.board.clone();
+        board[row][col] = EMPTY;
+        self.extend_captures(&board, piece, cell_from, cell_from, &mut Vec::new(), &mut Vec::new(), &mut moves);
+
+        moves
+    }
+
+    /// Explores every capture chain `piece` can make from `current`, pushing one compound
+    /// move per finished chain. Captured pieces stay on the board until the move is done,
+    /// so they block the way and cannot be jumped twice.
+    #[allow(clippy::too_many_arguments)]
+    fn extend_captures(&self, board: &[Vec<i8>], piece: i8, from: Square, current: Square,
+                       path: &mut Vec<Square>, eat: &mut Vec<(Square, i8)>, moves: &mut Vec<Move>) {
+        let mut piece = piece;
+
+        if !eat.is_empty() && !self.is_queen(piece) && current.row == self.get_last_row(piece) {
+            match self.rules.promotion_during_capture {
+                PromotionDuringCapture::Stop => {
+                    moves.push(Move { from, to: current, turn: self.turn, path: path.clone(), eat: eat.clone(), promotion: true });
+                    return;
                 }
+                PromotionDuringCapture::ContinueAsQueen => piece = self.crown(piece),
+                PromotionDuringCapture::ContinueAsPawn => {}
             }
         }
 
-        eated_square = None;
-        for i in 1..BOARD_SIZE {
-            if row >= i && col + i < BOARD_SIZE {
-                if self.board[row - i][col + i] == EMPTY {
-                    moves.push(Move { from: cell_from.clone(), to: Square{ row: row - i, col: col + i }, turn: self.turn, eat: eated_square.clone(), promotion: false });
-                }
-                else if self.board[row - i][col + i] * self.board[row][col] < 0 && eated_square.is_none() {
-                    eated_square = Some((Square{ row: row - i, col: col + i }, self.get_piece_at(row - i, col + i)));
-                }
-                else {
-                    break;
-                }
+        let is_captured = |square: &Square| eat.iter().any(|(eaten, _)| eaten == square);
+        let mut jumps = self.get_jumps(board, piece, current);
+        jumps.retain(|(over, _)| !is_captured(over));
+
+        // a flying queen has to land where the capture goes on, if any of the squares allows it
+        if self.is_queen(piece) && self.rules.flying_queens {
+            let continues: Vec<bool> = jumps.iter()
+                .map(|(over, to)| self.get_jumps(board, piece, *to).iter()
+                    .any(|(next, _)| next != over && !is_captured(next)))
+                .collect();
+            let continuing: Vec<Square> = jumps.iter()
+                .zip(continues.iter())
+                .filter(|(_, &continues)| continues)
+                .map(|((over, _), _)| *over)
+                .collect();
+            jumps = jumps.into_iter()
+                .zip(continues)
+                .filter(|((over, _), continues)| *continues || !continuing.contains(over))
+                .map(|(jump, _)| jump)
+                .collect();
+        }
+
+        if jumps.is_empty() {
+            if !eat.is_empty() {
+                let crowned = !self.is_queen(self.board[from.row][from.col])
+                    && (self.is_queen(piece) || current.row == self.get_last_row(piece));
+                moves.push(Move { from, to: current, turn: self.turn, path: path.clone(), eat: eat.clone(), promotion: crowned });
             }
+            return;
         }
 
-        eated_square = None;
-        for i in 1..BOARD_SIZE {
-            if row  >= i && col >= i {
-                if self.board[row - i][col - i] == EMPTY {
-                    moves.push(Move { from: cell_from.clone(), to: Square{ row: row - i, col: col - i }, turn: self.turn, eat: eated_square.clone(), promotion: false });
+        for (over, to) in jumps {
+            path.push(to);
+            eat.push((over, board[over.row][over.col]));
+            self.extend_captures(board, piece, from, to, path, eat, moves);
+            path.pop();
+            eat.pop();
+        }
+    }
+
+    /// Single jumps available to `piece` standing on `from`, as (captured square, landing square).
+    /// Flying queens run along the diagonal, jump the first piece they meet and may land on any
+    /// empty square behind it; every other piece jumps an adjacent piece.
+    fn get_jumps(&self, board: &[Vec<i8>], piece: i8, from: Square) -> Vec<(Square, Square)> {
+        let mut jumps = Vec::new();
+        let is_queen = self.is_queen(piece);
+        let flying = is_queen && self.rules.flying_queens;
+
+        for (d_row, d_col) in DIAGONALS {
+            if !is_queen && !self.rules.pawns_capture_backward && d_row != self.get_forward(piece) {
+                continue;
+            }
+
+            let mut over = self.offset(from, d_row, d_col);
+            while let Some(square) = over {
+                if !flying || board[square.row][square.col] != EMPTY {
+                    break;
                 }
-                else if self.board[row - i][col - i] * self.board[row][col] < 0 && eated_square.is_none() {
-                    eated_square = Some((Square{ row: row - i, col: col - i }, self.get_piece_at(row - i, col - i)));
+                over = self.offset(square, d_row, d_col);
+            }
+
+            let Some(over) = over else {
+                continue;
+            };
+            let target = board[over.row][over.col];
+            if target * piece >= 0 || (!is_queen && self.is_queen(target) && !self.rules.pawns_capture_queens) {
+                continue;
+            }
+
+            let mut landing = self.offset(over, d_row, d_col);
+            while let Some(to) = landing {
+                if board[to.row][to.col] != EMPTY {
+                    break;
                 }
-                else {
+                jumps.push((over, to));
+                if !flying {
                     break;
                 }
+                landing = self.offset(to, d_row, d_col);
             }
         }
 
-        moves
+        jumps
     }
 
-    fn is_black_piece(&self, piece: i8) -> bool {
-        piece < 0
+    /// Returns the square at the given offset, or `None` if it falls outside the board.
+    fn offset(&self, square: Square, d_row: isize, d_col: isize) -> Option<Square> {
+        let row = square.row.checked_add_signed(d_row)?;
+        let col = square.col.checked_add_signed(d_col)?;
+
+        if row < self.rules.board_size && col < self.rules.board_size {
+            Some(Square { row, col })
+        } else {
+            None
+        }
     }
 
-    fn is_white_piece(&self, piece: i8) -> bool {
-        piece > 0
+    fn get_forward(&self, piece: i8) -> isize {
+        if self.is_black_piece(piece) { -1 } else { 1 }
     }
 
-    fn get_piece_at(&self, row: usize, col: usize) -> i8 {
-        self.board[row][col]
+    fn get_last_row(&self, piece: i8) -> usize {
+        if self.is_black_piece(piece) { 0 } else { self.rules.board_size - 1 }
+    }
+
+    fn crown(&self, piece: i8) -> i8 {
+        match piece {
+            WHITE_PAWN => WHITE_QUEEN,
+            BLACK_PAWN => BLACK_QUEEN,
+            _ => piece
+        }
+    }
+
+    fn uncrown(&self, piece: i8) -> i8 {
+        match piece {
+            WHITE_QUEEN => WHITE_PAWN,
+            BLACK_QUEEN => BLACK_PAWN,
+            _ => piece
+        }
+    }
+
+    fn set_piece(&mut self, square: Square, piece: i8) {
+        self.hash ^= zobrist::piece_key(square, self.board[square.row][square.col]) ^ zobrist::piece_key(square, piece);
+        self.board[square.row][square.col] = piece;
+        if let Some(bitboards) = self.bitboards.as_mut() {
+            bitboards.set(square, piece);
+        }
+    }
+
+    fn is_black_piece(&self, piece: i8) -> bool {
+        piece < 0
+    }
+
+    fn is_queen(&self, piece: i8) -> bool {
+        piece == WHITE_QUEEN || piece == BLACK_QUEEN
     }
 
     fn switch_turn(&mut self) {
@@ -376,5 +932,544 @@ impl GameManager {
         } else {
             self.turn = Turn::Red;
         }
+        self.hash ^= zobrist::side_key();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use std::time::Duration;
+    use rand::SeedableRng;
+    use super::*;
+
+    #[test]
+    fn undo_restores_state_after_every_move() {
+        for seed in 0..50 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut game_manager = GameManager::new();
+
+            while game_manager.get_game_status() == GameStatus::Playing {
+                let moves = game_manager.get_legal_moves().clone();
+                let m = moves.choose(&mut rng).unwrap();
+
+                let before = game_manager.clone();
+                game_manager.do_move(m);
+                assert_eq!(game_manager.get_hash(), game_manager.compute_hash());
+                assert_eq!(game_manager.undo_move().as_ref(), Some(m));
+                assert_eq!(game_manager, before, "seed {} move {:?}", seed, m);
+
+                game_manager.do_move(m);
+            }
+        }
+    }
+
+    #[test]
+    fn undo_whole_game_returns_to_start() {
+        for seed in 0..50 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut game_manager = GameManager::new();
+            let mut positions = vec![game_manager.clone()];
+
+            while game_manager.get_game_status() == GameStatus::Playing {
+                let moves = game_manager.get_legal_moves().clone();
+                game_manager.do_move(moves.choose(&mut rng).unwrap());
+                positions.push(game_manager.clone());
+            }
+
+            positions.pop();
+            while let Some(expected) = positions.pop() {
+                assert!(game_manager.undo_move().is_some());
+                assert_eq!(game_manager, expected, "seed {}", seed);
+            }
+            assert_eq!(game_manager.undo_move(), None);
+        }
+    }
+
+    /// Known perft counts from the start position. English, Russian, Brazilian and
+    /// International match the published tables; the rest guard against regressions.
+    const START_PERFT: [(Variant, &[u64]); 8] = [
+        (Variant::Spanish, &[7, 49, 302, 1469, 7361, 36473, 177532]),
+        (Variant::Portuguese, &[7, 49, 302, 1469, 7361, 36473, 177532]),
+        (Variant::Italian, &[7, 49, 302, 1469, 7361, 36473]),
+        (Variant::English, &[7, 49, 302, 1469, 7361, 36768, 179740]),
+        (Variant::Russian, &[7, 49, 302, 1469, 7482, 37986]),
+        (Variant::Brazilian, &[7, 49, 302, 1469, 7473, 37628]),
+        (Variant::International, &[9, 81, 658, 4265]),
+        (Variant::Canadian, &[11, 121, 1222]),
+    ];
+
+    /// A flying queen with a two piece chain that must land where the capture continues.
+    const PERFT_QUEEN_CHAINS: [u64; 5] = [2, 8, 80, 414, 3201];
+
+    /// A pawn capturing onto the last row with another capture available from there.
+    const PERFT_PROMOTION_DURING_CAPTURE: [(Variant, &[u64]); 3] = [
+        (Variant::Spanish, &[1, 3, 11, 26, 190]),
+        (Variant::Russian, &[3, 3, 19, 33, 234]),
+        (Variant::Brazilian, &[1, 1, 1, 0, 0]),
+    ];
+
+    /// Two captures of two pieces, only one of them taking a queen.
+    const PERFT_MAJORITY_RULE: [(Variant, &[u64]); 3] = [
+        (Variant::Spanish, &[1, 1, 1, 2, 8]),
+        (Variant::Brazilian, &[2, 2, 4, 26, 151]),
+        (Variant::English, &[2, 2, 4, 14, 44]),
+    ];
+
+    /// Builds a position from (row, col, piece) triples.
+    fn position(variant: Variant, turn: Turn, pieces: &[(usize, usize, i8)]) -> GameManager {
+        let mut game_manager = GameManager::with_variant(variant);
+        let size = game_manager.get_board_size();
+
+        for row in 0..size {
+            for col in 0..size {
+                game_manager.set_piece(Square { row, col }, EMPTY);
+            }
+        }
+        for &(row, col, piece) in pieces {
+            game_manager.set_piece(Square { row, col }, piece);
+        }
+        if game_manager.turn != turn {
+            game_manager.switch_turn();
+        }
+        game_manager.moves_ready = false;
+
+        game_manager
+    }
+
+    fn assert_perft(game_manager: &mut GameManager, expected: &[u64]) {
+        for (depth, &nodes) in expected.iter().enumerate() {
+            let depth = depth as u32 + 1;
+            assert_eq!(game_manager.perft(depth), nodes, "{:?} depth {}", game_manager.rules.variant, depth);
+        }
+    }
+
+    #[test]
+    fn perft_start_positions() {
+        for (variant, expected) in START_PERFT {
+            let mut game_manager = GameManager::with_variant(variant);
+            let before = game_manager.clone();
+            assert_perft(&mut game_manager, expected);
+            assert_eq!(game_manager, before);
+        }
+    }
+
+    #[test]
+    fn perft_divide_adds_up_to_perft() {
+        let mut game_manager = GameManager::with_variant(Variant::English);
+        let divide = game_manager.perft_divide(4);
+        assert_eq!(divide.len(), 7);
+        assert_eq!(divide.iter().map(|(_, nodes)| nodes).sum::<u64>(), 1469);
+        assert!(game_manager.perft_divide(0).iter().all(|&(_, nodes)| nodes == 1));
+    }
+
+    #[test]
+    fn perft_queen_chains() {
+        let pieces = [
+            (0, 0, WHITE_QUEEN), (1, 5, WHITE_PAWN),
+            (2, 2, BLACK_PAWN), (3, 1, BLACK_PAWN), (5, 3, BLACK_PAWN), (6, 6, BLACK_PAWN), (7, 7, BLACK_QUEEN),
+        ];
+
+        let mut game_manager = position(Variant::Spanish, Turn::Red, &pieces);
+        assert_perft(&mut game_manager, &PERFT_QUEEN_CHAINS);
+    }
+
+    #[test]
+    fn perft_promotion_during_capture() {
+        let pieces = [
+            (0, 0, WHITE_PAWN), (5, 1, WHITE_PAWN),
+            (6, 2, BLACK_PAWN), (6, 4, BLACK_PAWN), (7, 7, BLACK_PAWN),
+        ];
+
+        for (variant, expected) in PERFT_PROMOTION_DURING_CAPTURE {
+            let mut game_manager = position(variant, Turn::Red, &pieces);
+            assert_perft(&mut game_manager, expected);
+        }
+    }
+
+    #[test]
+    fn perft_majority_rule() {
+        let pieces = [
+            (0, 0, WHITE_PAWN), (2, 2, WHITE_PAWN), (2, 6, WHITE_PAWN),
+            (3, 3, BLACK_PAWN), (3, 5, BLACK_QUEEN), (5, 5, BLACK_PAWN), (7, 1, BLACK_PAWN),
+        ];
+
+        for (variant, expected) in PERFT_MAJORITY_RULE {
+            let mut game_manager = position(variant, Turn::Red, &pieces);
+            assert_perft(&mut game_manager, expected);
+        }
+    }
+
+    /// Pieces taken by each legal move, sorted.
+    fn capture_sizes(game_manager: &mut GameManager) -> Vec<usize> {
+        let mut sizes: Vec<usize> = game_manager.get_legal_moves().iter().map(|m| m.eat.len()).collect();
+        sizes.sort();
+        sizes
+    }
+
+    #[test]
+    fn captures_follow_the_majority_rule() {
+        // one piece to the left, two in a row to the right
+        let pieces = [(2, 2, WHITE_PAWN), (3, 1, BLACK_PAWN), (3, 3, BLACK_PAWN), (5, 5, BLACK_PAWN)];
+
+        assert_eq!(capture_sizes(&mut position(Variant::Spanish, Turn::Red, &pieces)), [2]);
+        assert_eq!(capture_sizes(&mut position(Variant::Brazilian, Turn::Red, &pieces)), [2]);
+        // English lets either capture be chosen
+        assert_eq!(capture_sizes(&mut position(Variant::English, Turn::Red, &pieces)), [1, 2]);
+    }
+
+    #[test]
+    fn captures_prefer_queens_on_equal_count() {
+        // a pawn or a queen to take, one piece each
+        let pieces = [(2, 2, WHITE_PAWN), (3, 1, BLACK_PAWN), (3, 3, BLACK_QUEEN)];
+
+        let mut spanish = position(Variant::Spanish, Turn::Red, &pieces);
+        let moves = spanish.get_legal_moves().clone();
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].eat, [(Square { row: 3, col: 3 }, BLACK_QUEEN)]);
+        assert_eq!(position(Variant::Brazilian, Turn::Red, &pieces).get_legal_moves().len(), 2);
+
+        // a pawn or a queen taking one pawn each: the queen captures
+        let pieces = [(2, 2, WHITE_PAWN), (3, 3, BLACK_PAWN), (1, 7, WHITE_QUEEN), (3, 5, BLACK_PAWN)];
+        let mut spanish = position(Variant::Spanish, Turn::Red, &pieces);
+        let moves = spanish.get_legal_moves().clone();
+        assert!(!moves.is_empty());
+        assert!(moves.iter().all(|m| m.from == Square { row: 1, col: 7 }));
+    }
+
+    #[test]
+    fn chains_are_one_compound_move() {
+        let pieces = [(2, 2, WHITE_PAWN), (3, 3, BLACK_PAWN), (5, 5, BLACK_QUEEN), (7, 1, BLACK_PAWN)];
+        let mut game_manager = position(Variant::Spanish, Turn::Red, &pieces);
+        let before = game_manager.clone();
+
+        let moves = game_manager.get_legal_moves().clone();
+        assert_eq!(moves.len(), 1);
+        let m = &moves[0];
+        assert_eq!((m.from, m.to), (Square { row: 2, col: 2 }, Square { row: 6, col: 6 }));
+        assert_eq!(m.path, [Square { row: 4, col: 4 }, Square { row: 6, col: 6 }]);
+        assert_eq!(m.eat, [(Square { row: 3, col: 3 }, BLACK_PAWN), (Square { row: 5, col: 5 }, BLACK_QUEEN)]);
+
+        game_manager.do_move(m);
+        assert_eq!(game_manager.board[3][3], EMPTY);
+        assert_eq!(game_manager.board[5][5], EMPTY);
+        assert_eq!(game_manager.board[6][6], WHITE_PAWN);
+        assert_eq!(game_manager.get_turn(), Turn::Black);
+
+        game_manager.undo_move();
+        assert_eq!(game_manager, before);
+    }
+
+    #[test]
+    fn flying_queens_remove_captured_pieces_at_the_end() {
+        // after taking (2, 2), (5, 3) and (5, 1) the queen stands on (4, 0); the way to (1, 3)
+        // crosses (2, 2), which stays on the board until the move is over and blocks it
+        let pieces = [
+            (0, 0, WHITE_QUEEN),
+            (2, 2, BLACK_PAWN), (5, 3, BLACK_PAWN), (5, 1, BLACK_PAWN), (1, 3, BLACK_PAWN),
+        ];
+        let mut game_manager = position(Variant::Spanish, Turn::Red, &pieces);
+
+        let moves = game_manager.get_legal_moves().clone();
+        assert_eq!(moves.len(), 1);
+        let m = &moves[0];
+        assert_eq!(m.to, Square { row: 4, col: 0 });
+        assert_eq!(m.path, [Square { row: 4, col: 4 }, Square { row: 6, col: 2 }, Square { row: 4, col: 0 }]);
+        assert_eq!(m.eat.len(), 3);
+        assert!(!m.eat.iter().any(|(square, _)| *square == Square { row: 1, col: 3 }));
+
+        game_manager.do_move(m);
+        assert_eq!(game_manager.board[1][3], BLACK_PAWN);
+        assert_eq!(game_manager.to_fen(), "B:WK13:B26");
+    }
+
+    /// Plays `plies` queen moves that reach new positions and leave no capture to the other
+    /// side, so nothing but the draw counters changes.
+    fn wander(game_manager: &mut GameManager, plies: usize) {
+        let mut seen = vec![game_manager.get_hash()];
+
+        for _ in 0..plies {
+            let moves = game_manager.get_legal_moves().clone();
+            let m = moves.iter()
+                .filter(|m| !m.is_capture() && game_manager.is_queen(game_manager.board[m.from.row][m.from.col]))
+                .find(|m| {
+                    let mut next = game_manager.clone();
+                    next.do_move(m);
+                    !seen.contains(&next.get_hash()) && !next.get_legal_moves().iter().any(|m| m.is_capture())
+                })
+                .expect("no quiet queen move left")
+                .clone();
+
+            game_manager.do_move(&m);
+            seen.push(game_manager.get_hash());
+        }
+    }
+
+    #[test]
+    fn shuffling_queens_draw_by_repetition() {
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "W:W21,K32:B12,K1").unwrap();
+
+        for (ply, text) in ["32-28", "1-5", "28-32", "5-1", "32-28", "1-5", "28-32", "5-1"].iter().enumerate() {
+            assert_eq!(game_manager.get_game_status(), GameStatus::Playing, "ply {}", ply);
+            let m = game_manager.find_move(text).unwrap();
+            game_manager.do_move(&m);
+        }
+        // the starting position is back for the third time
+        assert_eq!(game_manager.get_game_status(), GameStatus::Draw(DrawReason::Repetition));
+    }
+
+    #[test]
+    fn queen_moves_alone_draw_by_no_progress() {
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "W:W21,K32:B12,K1").unwrap();
+
+        let limit = game_manager.rules.no_progress_limit.unwrap() as usize;
+        wander(&mut game_manager, limit - 1);
+        assert_eq!(game_manager.get_game_status(), GameStatus::Playing);
+        wander(&mut game_manager, 1);
+        assert_eq!(game_manager.get_game_status(), GameStatus::Draw(DrawReason::NoProgress));
+    }
+
+    #[test]
+    fn pawn_moves_and_captures_reset_no_progress() {
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "W:W21,K32:B12,K1").unwrap();
+        wander(&mut game_manager, 10);
+        assert_eq!(game_manager.moves_without_progress, 10);
+
+        let pawn_move = game_manager.get_legal_moves().iter()
+            .find(|m| m.from == Square { row: 2, col: 0 })
+            .unwrap()
+            .clone();
+        game_manager.do_move(&pawn_move);
+        assert_eq!(game_manager.moves_without_progress, 0);
+
+        // the black queen steps where it can be taken
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "W:W21,K32:B12,K1").unwrap();
+        wander(&mut game_manager, 3);
+        let offer = game_manager.get_legal_moves().clone().into_iter()
+            .find(|m| {
+                let mut next = game_manager.clone();
+                next.do_move(m);
+                next.get_legal_moves().iter().any(|m| m.is_capture())
+            })
+            .unwrap();
+        game_manager.do_move(&offer);
+        assert_eq!(game_manager.moves_without_progress, 4);
+
+        let capture = game_manager.get_legal_moves()[0].clone();
+        assert!(capture.is_capture());
+        game_manager.do_move(&capture);
+        assert_eq!(game_manager.moves_without_progress, 0);
+    }
+
+    #[test]
+    fn three_queens_against_one_draw_by_count() {
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "W:WK29,K30,K31:BK1").unwrap();
+
+        let limit = game_manager.rules.queen_endgame_limit.unwrap() as usize;
+        wander(&mut game_manager, limit - 1);
+        assert_eq!(game_manager.get_game_status(), GameStatus::Playing);
+        wander(&mut game_manager, 1);
+        assert_eq!(game_manager.get_game_status(), GameStatus::Draw(DrawReason::QueenEndgame));
+
+        // with fewer or more queens the count does not start
+        for fen in ["W:WK29:BK1", "W:WK29,K30:BK1", "W:WK29,K30,K31,K32:BK1"] {
+            let mut game_manager = GameManager::from_fen(Variant::Spanish, fen).unwrap();
+            wander(&mut game_manager, 4);
+            assert_eq!(game_manager.queen_endgame_moves, 0, "{}", fen);
+        }
+
+        // English checkers has no such rule: three kings have to win
+        let mut game_manager = GameManager::from_fen(Variant::English, "W:WK29,K30,K31:BK1").unwrap();
+        wander(&mut game_manager, 30);
+        assert_eq!(game_manager.get_game_status(), GameStatus::Playing);
+    }
+
+    #[test]
+    fn draw_limits_of_each_variant() {
+        let limits = [
+            (Variant::Spanish, Some(50), Some(24)),
+            (Variant::Portuguese, Some(50), Some(24)),
+            (Variant::Italian, Some(50), Some(24)),
+            (Variant::English, Some(80), None),
+            (Variant::Russian, Some(30), Some(30)),
+            (Variant::Brazilian, Some(50), Some(32)),
+            (Variant::International, Some(50), Some(32)),
+            (Variant::Canadian, Some(50), Some(32)),
+        ];
+        assert_eq!(limits.len(), Variant::ALL.len());
+
+        for (variant, no_progress, queen_endgame) in limits {
+            let rules = variant.rules();
+            assert_eq!((rules.no_progress_limit, rules.queen_endgame_limit), (no_progress, queen_endgame), "{:?}", variant);
+            assert_eq!((rules.queen_endgame_queens, rules.repetition_limit), (3, 3), "{:?}", variant);
+        }
+    }
+
+    /// The same game without bitboards, generating its moves square by square.
+    fn without_bitboards(game_manager: &GameManager) -> GameManager {
+        let mut slow = game_manager.clone();
+        slow.bitboards = None;
+        slow.moves_ready = false;
+        slow
+    }
+
+    #[test]
+    fn bitboards_generate_the_same_moves() {
+        for variant in Variant::ALL.into_iter().filter(|variant| variant.rules().board_size == BITBOARD_SIZE) {
+            for seed in 0..20 {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut game_manager = GameManager::with_variant(variant);
+
+                while game_manager.get_game_status() == GameStatus::Playing {
+                    let moves = game_manager.get_legal_moves().clone();
+                    // the same moves, not always in the same order
+                    let slow_moves = without_bitboards(&game_manager).get_legal_moves().clone();
+                    assert_eq!(slow_moves.len(), moves.len(), "{} seed {}", variant, seed);
+                    assert!(slow_moves.iter().all(|m| moves.contains(m)), "{} seed {}", variant, seed);
+                    game_manager.do_move(moves.choose(&mut rng).unwrap());
+                }
+            }
+        }
+    }
+
+    /// Perft speed with and without bitboards; run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bitboards_are_faster() {
+        let depth = 8;
+        let mut fast = GameManager::with_variant(Variant::English);
+        let mut slow = without_bitboards(&fast);
+
+        let start = std::time::Instant::now();
+        let nodes = fast.perft(depth);
+        let fast_time = start.elapsed();
+        let start = std::time::Instant::now();
+        assert_eq!(slow.perft(depth), nodes);
+        let slow_time = start.elapsed();
+
+        println!("perft {} = {}: bitboards {:?}, squares {:?}, {:.1}x",
+                 depth, nodes, fast_time, slow_time, slow_time.as_secs_f64() / fast_time.as_secs_f64());
+        assert!(fast_time < slow_time);
+    }
+
+    #[test]
+    fn fen_round_trips_start_positions() {
+        for variant in Variant::ALL {
+            let game_manager = GameManager::with_variant(variant);
+            let loaded = GameManager::from_fen(variant, &game_manager.to_fen()).unwrap();
+            assert_eq!(loaded, game_manager, "{}", variant);
+        }
+
+        let start = GameManager::from_fen(Variant::English, "B:W21-32:B1-12").unwrap();
+        assert_eq!(start, GameManager::with_variant(Variant::English));
+    }
+
+    #[test]
+    fn fen_sets_up_position() {
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "W:W26,K29:B22,7.").unwrap();
+
+        assert_eq!(game_manager.get_turn(), Turn::Red);
+        assert_eq!(game_manager.board[0][0], WHITE_QUEEN);
+        assert_eq!(game_manager.board[1][3], WHITE_PAWN);
+        assert_eq!(game_manager.board[2][2], BLACK_PAWN);
+        assert_eq!(game_manager.board[6][4], BLACK_PAWN);
+        assert_eq!(game_manager.get_hash(), game_manager.compute_hash());
+        assert_eq!(game_manager.to_fen(), "W:W26,K29:B7,22");
+        assert_eq!(game_manager.get_legal_moves().len(), 1);
+    }
+
+    #[test]
+    fn fen_rejects_impossible_positions() {
+        let cases = [
+            ("X:W21:B1", FenError::Syntax("unknown side 'X'".to_string())),
+            ("W", FenError::Syntax("no piece lists".to_string())),
+            ("W:W21,a:B1", FenError::Syntax("bad square 'a'".to_string())),
+            ("W:W33:B1", FenError::SquareOutOfRange(33)),
+            ("W:W21:B21", FenError::DuplicateSquare(21)),
+            ("W:W2:B10", FenError::PawnOnLastRow(2)),
+            ("W:W20:B30", FenError::PawnOnLastRow(30)),
+            ("W:W1-13:B30", FenError::TooManyPieces(Turn::Red)),
+        ];
+
+        for (fen, error) in cases {
+            assert_eq!(GameManager::from_fen(Variant::Spanish, fen).unwrap_err(), error, "{}", fen);
+        }
+        assert!(GameManager::from_fen(Variant::Spanish, "W:WK2:BK30").is_ok());
+    }
+
+    /// A move written as square numbers, with no captured pieces filled in.
+    fn numbered_move(turn: Turn, squares: &[usize]) -> Move {
+        let square = |number| notation::number_to_square(number, 8).unwrap();
+        let path: Vec<Square> = squares[1..].iter().map(|&number| square(number)).collect();
+
+        Move {
+            turn,
+            from: square(squares[0]),
+            to: *path.last().unwrap(),
+            path,
+            eat: Vec::new(),
+            promotion: false,
+        }
+    }
+
+    #[test]
+    fn try_move_rejects_with_reason() {
+        let mut game_manager = GameManager::new();
+        assert_eq!(game_manager.try_move(&numbered_move(Turn::Black, &[9, 13])), Err(MoveError::WrongTurn));
+        assert_eq!(game_manager.try_move(&numbered_move(Turn::Red, &[9, 13])), Err(MoveError::WrongTurn));
+        assert_eq!(game_manager.try_move(&numbered_move(Turn::Red, &[22, 19])), Err(MoveError::NotLegal));
+
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "W:W22:B18").unwrap();
+        assert_eq!(game_manager.try_move(&numbered_move(Turn::Red, &[22, 17])), Err(MoveError::CaptureMandatory));
+
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "W:W22:B9,10,17,18").unwrap();
+        assert_eq!(game_manager.try_move(&numbered_move(Turn::Red, &[22, 15])), Err(MoveError::MustContinueChain));
+        assert_eq!(game_manager.try_move(&numbered_move(Turn::Red, &[22, 6])), Err(MoveError::NotLegal));
+        assert_eq!(game_manager.try_move(&numbered_move(Turn::Red, &[22, 26])), Err(MoveError::CaptureMandatory));
+
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "W:W22:B5").unwrap();
+        assert_eq!(game_manager.try_move(&numbered_move(Turn::Red, &[22, 26])), Err(MoveError::NotLegal));
+
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "B:W22:B").unwrap();
+        assert_eq!(game_manager.try_move(&numbered_move(Turn::Black, &[9, 13])), Err(MoveError::GameOver(GameStatus::RedWins)));
+    }
+
+    #[test]
+    fn try_move_plays_the_legal_move() {
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "W:W22:B9,10,17,18").unwrap();
+        let before = game_manager.clone();
+
+        assert_eq!(game_manager.try_move(&numbered_move(Turn::Red, &[22, 13, 6])), Ok(()));
+        assert_eq!(game_manager.to_fen(), "B:W6:B10,18");
+        assert_eq!(game_manager.get_played_moves()[0].eat.len(), 2);
+
+        game_manager.undo_move();
+        assert_eq!(game_manager, before);
+
+        // a step only needs its ends
+        let mut game_manager = GameManager::new();
+        let mut step = numbered_move(Turn::Red, &[22, 18]);
+        step.path.clear();
+        assert_eq!(game_manager.try_move(&step), Ok(()));
+        assert_eq!(game_manager.get_turn(), Turn::Black);
+    }
+
+    #[test]
+    fn try_move_presses_the_clock() {
+        let mut game_manager = GameManager::new();
+        game_manager.set_clock(Clock::new("60+5".parse().unwrap()));
+        let m = game_manager.get_legal_moves()[0].clone();
+        assert_eq!(game_manager.try_move(&m), Ok(()));
+        assert!(game_manager.get_clock().unwrap().get_remaining(m.turn) > Duration::from_secs(60));
+
+        // a side out of time loses instead of moving
+        let turn = game_manager.get_turn();
+        let mut clock = Clock::new("60".parse().unwrap());
+        clock.end_move(turn, Duration::from_secs(61));
+        game_manager.set_clock(clock);
+        let m = game_manager.get_legal_moves()[0].clone();
+        assert_eq!(game_manager.try_move(&m), Err(MoveError::GameOver(GameStatus::LostOnTime(turn))));
+        assert_eq!(game_manager.get_game_status().get_winner(), Some(turn.opposite()));
     }
 }
@@ -0,0 +1,207 @@
+//! Matches between two brains, to tell whether a newly trained network is stronger.
+//!
+//! Games are played in pairs from the same random opening, each brain playing both colors,
+//! so neither gets the better side of an opening.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::comun::{GameStatus, Turn};
+use crate::game_manager::GameManager;
+use crate::ia::brain::Brain;
+use crate::rules::Variant;
+
+/// Makes a fresh brain for every game.
+pub type BrainFactory<'a> = &'a (dyn Fn() -> Box<dyn Brain> + Sync);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArenaConfig {
+    pub variant: Variant,
+    /// Games played, rounded up to whole pairs.
+    pub games: usize,
+    /// Random moves played before the brains take over.
+    pub opening_plies: usize,
+    /// Pair `i` opens with seed `seed + i`.
+    pub seed: u64,
+    pub threads: usize,
+    /// Score the candidate needs to be promoted, from 0 to 1.
+    pub threshold: f64,
+}
+
+impl ArenaConfig {
+    pub fn new(variant: Variant, games: usize) -> ArenaConfig {
+        ArenaConfig {
+            variant,
+            games,
+            opening_plies: 4,
+            seed: 0,
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            threshold: 0.55,
+        }
+    }
+}
+
+/// Results of the candidate against the champion.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArenaResult {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl ArenaResult {
+    pub fn get_games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Points of the candidate per game, a draw being half a point.
+    pub fn get_score(&self) -> f64 {
+        if self.get_games() == 0 {
+            return 0.5;
+        }
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.get_games() as f64
+    }
+
+    /// 95% confidence interval of the score, from the spread of the game results.
+    pub fn get_interval(&self) -> (f64, f64) {
+        let games = self.get_games() as f64;
+        if games == 0.0 {
+            return (0.0, 1.0);
+        }
+
+        let score = self.get_score();
+        let variance = (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2)) / games;
+        let margin = 1.96 * (variance / games).sqrt();
+        ((score - margin).max(0.0), (score + margin).min(1.0))
+    }
+
+    pub fn passes(&self, threshold: f64) -> bool {
+        self.get_score() >= threshold
+    }
+}
+
+impl fmt::Display for ArenaResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (low, high) = self.get_interval();
+        write!(f, "+{} ={} -{} score {:.3} [{:.3}, {:.3}]", self.wins, self.draws, self.losses,
+               self.get_score(), low, high)
+    }
+}
+
+/// A game from `opening_plies` random moves, the same for every `seed`.
+pub fn random_opening(variant: Variant, opening_plies: usize, seed: u64) -> GameManager {
+    let mut game_manager = GameManager::with_variant(variant);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    for _ in 0..opening_plies {
+        if game_manager.get_game_status() != GameStatus::Playing {
+            break;
+        }
+        match game_manager.get_legal_moves().choose(&mut rng).cloned() {
+            Some(m) => game_manager.do_move(&m),
+            None => break,
+        }
+    }
+    game_manager
+}
+
+/// Plays `game_manager` to its end, `red` and `black` choosing the moves.
+pub fn play_out(mut game_manager: GameManager, red: &mut dyn Brain, black: &mut dyn Brain) -> GameStatus {
+    while game_manager.get_game_status() == GameStatus::Playing {
+        let turn = game_manager.get_turn();
+        let best = match turn {
+            Turn::Red => red.get_best_move(game_manager.clone()),
+            Turn::Black => black.get_best_move(game_manager.clone()),
+        };
+        match best {
+            Some(m) => game_manager.do_move(&m),
+            // the side to move is blocked and loses
+            None => return if turn == Turn::Red { GameStatus::BlackWins } else { GameStatus::RedWins },
+        }
+    }
+    game_manager.get_game_status()
+}
+
+/// Plays the match, calling `on_game` with the number, the candidate's color and the result
+/// of every game as it ends.
+pub fn play_match(candidate: BrainFactory, champion: BrainFactory, config: &ArenaConfig,
+                  on_game: impl Fn(usize, Turn, &GameStatus) + Sync) -> ArenaResult {
+    let games = config.games.div_ceil(2) * 2;
+    let next = AtomicUsize::new(0);
+    let result = Mutex::new(ArenaResult::default());
+
+    thread::scope(|scope| {
+        for _ in 0..config.threads.max(1) {
+            scope.spawn(|| loop {
+                let game = next.fetch_add(1, Ordering::Relaxed);
+                if game >= games {
+                    break;
+                }
+
+                // the candidate takes Red in the first game of a pair and Black in the second
+                let color = if game.is_multiple_of(2) { Turn::Red } else { Turn::Black };
+                let opening = random_opening(config.variant, config.opening_plies, config.seed + (game / 2) as u64);
+                let (mut ours, mut theirs) = (candidate(), champion());
+                let status = match color {
+                    Turn::Red => play_out(opening, ours.as_mut(), theirs.as_mut()),
+                    Turn::Black => play_out(opening, theirs.as_mut(), ours.as_mut()),
+                };
+                on_game(game, color, &status);
+
+                let mut result = result.lock().unwrap();
+                match status.get_winner() {
+                    Some(winner) if winner == color => result.wins += 1,
+                    Some(_) => result.losses += 1,
+                    None => result.draws += 1,
+                }
+            });
+        }
+    });
+
+    result.into_inner().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ia::mcts::Mcts;
+
+    #[test]
+    fn scores_and_intervals() {
+        let even = ArenaResult { wins: 30, draws: 40, losses: 30 };
+        assert_eq!(even.get_score(), 0.5);
+        let (low, high) = even.get_interval();
+        assert!(low < 0.5 && high > 0.5);
+        assert!((0.5 - low - (high - 0.5)).abs() < 1e-9);
+        assert!(!even.passes(0.55));
+
+        let strong = ArenaResult { wins: 60, draws: 20, losses: 20 };
+        assert_eq!(strong.get_score(), 0.7);
+        assert!(strong.passes(0.55));
+        assert!(strong.get_interval().0 > 0.6);
+    }
+
+    #[test]
+    fn every_game_is_counted_once() {
+        let candidate = || Box::new(Mcts::with_iterations(30)) as Box<dyn Brain>;
+        let champion = || Box::new(Mcts::with_iterations(10)) as Box<dyn Brain>;
+        let config = ArenaConfig { threads: 2, ..ArenaConfig::new(Variant::English, 3) };
+
+        let colors = Mutex::new(Vec::new());
+        let result = play_match(&candidate, &champion, &config, |game, color, _| colors.lock().unwrap().push((game, color)));
+
+        assert_eq!(result.get_games(), 4);
+        let mut colors = colors.into_inner().unwrap();
+        colors.sort_by_key(|&(game, _)| game);
+        assert_eq!(colors.iter().map(|&(_, color)| color).collect::<Vec<_>>(),
+                   [Turn::Red, Turn::Black, Turn::Red, Turn::Black]);
+    }
+}
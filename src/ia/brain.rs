@@ -1,3 +1,7 @@
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use crate::clock::TimeLeft;
 use crate::comun::Move;
 use crate::game_manager::GameManager;
 
@@ -5,6 +9,37 @@ pub const REDVALUE: i32 = 1;
 pub const BLACKVALUE: i32 = -1;
 pub const DRAWVALUE: i32 = 0;
 
+/// Budget of a search. With no limit at all the brain uses its own default budget.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub time: Option<Duration>,
+    pub nodes: Option<u64>,
+    /// Time left on the clock, for the brain to budget itself when `time` is not given.
+    pub clock: Option<TimeLeft>,
+    /// Search until stopped.
+    pub infinite: bool,
+}
+
+/// Progress of a search, reported while it runs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchInfo {
+    pub depth: u32,
+    /// Expected result for the side to move, from -1 (loss) to 1 (win).
+    pub score: f32,
+    pub nodes: u64,
+    pub time: Duration,
+    /// Best line found, starting with the move the search would play.
+    pub pv: Vec<Move>,
+}
+
 pub trait Brain: Send + Sync {
-    fn get_best_move(&mut self, game_manager: GameManager) -> Move;
+    /// The move to play, or `None` when the side to move has no legal move.
+    fn get_best_move(&mut self, game_manager: GameManager) -> Option<Move>;
+
+    /// Like `get_best_move`, within `limits`, returning early once `stop` is set and
+    /// calling `info` with the progress. Brains that cannot be limited just pick their move.
+    fn search(&mut self, game_manager: GameManager, _limits: &SearchLimits, _stop: &AtomicBool,
+              _info: &mut dyn FnMut(SearchInfo)) -> Option<Move> {
+        self.get_best_move(game_manager)
+    }
 }
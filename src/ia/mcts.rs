@@ -0,0 +1,381 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use crate::comun::{GameStatus, Move, Turn};
+use crate::game_manager::GameManager;
+use crate::ia::brain::{BLACKVALUE, Brain, DRAWVALUE, REDVALUE, SearchInfo, SearchLimits};
+use crate::rules::RuleSet;
+
+/// Playouts per move when the search has no limits.
+const DEFAULT_ITERATIONS: u32 = 10_000;
+/// UCB1 exploration constant; results go from -1 to 1.
+const EXPLORATION: f32 = std::f32::consts::SQRT_2;
+/// Nodes kept in the tree; past this the search goes on without growing it.
+const MAX_NODES: usize = 2_000_000;
+/// How often, in playouts, the search reports its progress.
+const INFO_INTERVAL: u32 = 1024;
+
+struct Node {
+    /// Move leading here from the parent, `None` at a fresh root.
+    m: Option<Move>,
+    /// Side that played `m`.
+    turn: Turn,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// Legal moves not expanded yet.
+    untried: Vec<Move>,
+    visits: u32,
+    /// Sum of the results for the side that played `m`.
+    reward: f32,
+}
+
+impl Node {
+    fn new(m: Option<Move>, turn: Turn, parent: Option<usize>, untried: Vec<Move>) -> Node {
+        Node { m, turn, parent, children: Vec::new(), untried, visits: 0, reward: 0.0 }
+    }
+
+    fn mean(&self) -> f32 {
+        if self.visits == 0 { 0.0 } else { self.reward / self.visits as f32 }
+    }
+}
+
+/// Monte Carlo Tree Search: grows a tree one node per playout, choosing where with UCT, and
+/// keeps the part of the tree under the position reached for the next move.
+pub struct Mcts {
+    iterations: u32,
+    /// The tree, its root at index 0.
+    nodes: Vec<Node>,
+    /// Moves from the start of the game to the root, the position they start from and the
+    /// rules they were played under, to find the root again in the next search.
+    root_moves: Vec<Move>,
+    start_hash: u64,
+    rules: RuleSet,
+    rng: StdRng,
+    position_counter: u64,
+}
+
+impl Default for Mcts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Result of a finished game, `REDVALUE`, `BLACKVALUE` or `DRAWVALUE`.
+fn status_value(status: &GameStatus) -> i32 {
+    match status.get_winner() {
+        Some(Turn::Red) => REDVALUE,
+        Some(Turn::Black) => BLACKVALUE,
+        None => DRAWVALUE,
+    }
+}
+
+/// `value` for `turn`: 1 for a win down to -1 for a loss.
+fn value_for(value: i32, turn: Turn) -> f32 {
+    match turn {
+        Turn::Red => (value * REDVALUE) as f32,
+        Turn::Black => (value * BLACKVALUE) as f32,
+    }
+}
+
+impl Mcts {
+    pub fn new() -> Self {
+        Self::with_iterations(DEFAULT_ITERATIONS)
+    }
+
+    /// Playouts per move when the search has no limits; more is stronger and slower.
+    pub fn with_iterations(iterations: u32) -> Self {
+        Mcts {
+            iterations,
+            nodes: Vec::new(),
+            root_moves: Vec::new(),
+            start_hash: 0,
+            rules: RuleSet::default(),
+            rng: StdRng::from_entropy(),
+            position_counter: 0,
+        }
+    }
+
+    /// Moves the root to the current position of `game_manager` if the tree holds it,
+    /// keeping what was learnt about it. Returns false when the tree has to start over.
+    fn reuse_tree(&mut self, game_manager: &GameManager) -> bool {
+        let played = game_manager.get_played_moves();
+        let root_len = self.root_moves.len();
+        // several variants share the start position but not their moves
+        if self.nodes.is_empty() || *game_manager.get_rules() != self.rules
+            || played.len() < root_len || played[..root_len] != self.root_moves[..] {
+            return false;
+        }
+
+        let mut start = game_manager.clone();
+        while start.undo_move().is_some() {}
+        if start.get_hash() != self.start_hash {
+            return false;
+        }
+
+        let mut node = 0;
+        for m in &played[root_len..] {
+            match self.nodes[node].children.iter().find(|&&child| self.nodes[child].m.as_ref() == Some(m)) {
+                Some(&child) => node = child,
+                None => return false,
+            }
+        }
+
+        self.reroot(node);
+        self.root_moves = played;
+        true
+    }
+
+    /// Keeps only the subtree under `root`, which becomes index 0.
+    fn reroot(&mut self, root: usize) {
+        if root == 0 {
+            return;
+        }
+
+        let mut old: Vec<Option<Node>> = std::mem::take(&mut self.nodes).into_iter().map(Some).collect();
+        let mut queue = VecDeque::from([(root, None)]);
+
+        while let Some((i, parent)) = queue.pop_front() {
+            let mut node = old[i].take().unwrap();
+            let index = self.nodes.len();
+            node.parent = parent;
+            for child in std::mem::take(&mut node.children) {
+                queue.push_back((child, Some(index)));
+            }
+            if let Some(parent) = parent {
+                self.nodes[parent].children.push(index);
+            }
+            self.nodes.push(node);
+        }
+    }
+
+    fn new_tree(&mut self, game_manager: &mut GameManager) {
+        let moves = game_manager.get_legal_moves().clone();
+        self.nodes = vec![Node::new(None, game_manager.get_turn().opposite(), None, moves)];
+        self.root_moves = game_manager.get_played_moves();
+        self.rules = *game_manager.get_rules();
+
+        let mut start = game_manager.clone();
+        while start.undo_move().is_some() {}
+        self.start_hash = start.get_hash();
+    }
+
+    /// The child of `node` with the best upper confidence bound.
+    fn select_child(&self, node: usize) -> usize {
+        let log_visits = (self.nodes[node].visits.max(1) as f32).ln();
+        let uct = |child: usize| {
+            let child = &self.nodes[child];
+            child.mean() + EXPLORATION * (log_visits / child.visits.max(1) as f32).sqrt()
+        };
+
+        *self.nodes[node].children.iter()
+            .max_by(|&&a, &&b| uct(a).partial_cmp(&uct(b)).unwrap_or(std::cmp::Ordering::Equal))
+            .unwrap()
+    }
+
+    fn most_visited(&self, node: usize) -> Option<usize> {
+        self.nodes[node].children.iter().copied().max_by_key(|&child| self.nodes[child].visits)
+    }
+
+    /// One playout: down the tree with UCT, one new node, a random game from there and its
+    /// result added to every node on the way.
+    fn iterate(&mut self, game_manager: &mut GameManager) {
+        let mut node = 0;
+        let mut depth = 0;
+
+        while self.nodes[node].untried.is_empty() && !self.nodes[node].children.is_empty() {
+            node = self.select_child(node);
+            let m = self.nodes[node].m.clone().unwrap();
+            game_manager.do_move(&m);
+            depth += 1;
+        }
+
+        if !self.nodes[node].untried.is_empty() && self.nodes.len() < MAX_NODES {
+            let untried = &mut self.nodes[node].untried;
+            let m = untried.swap_remove(self.rng.gen_range(0..untried.len()));
+            game_manager.do_move(&m);
+            depth += 1;
+
+            let moves = if game_manager.get_game_status() == GameStatus::Playing {
+                game_manager.get_legal_moves().clone()
+            } else {
+                Vec::new()
+            };
+            let child = self.nodes.len();
+            self.nodes.push(Node::new(Some(m), game_manager.get_turn().opposite(), Some(node), moves));
+            self.nodes[node].children.push(child);
+            node = child;
+        }
+
+        let value = self.play_random_game(game_manager);
+
+        let mut current = Some(node);
+        while let Some(i) = current {
+            let node = &mut self.nodes[i];
+            node.visits += 1;
+            node.reward += value_for(value, node.turn);
+            current = node.parent;
+        }
+
+        for _ in 0..depth {
+            game_manager.undo_move();
+        }
+    }
+
+    /// Plays random moves to the end of the game and takes them back.
+    fn play_random_game(&mut self, game_manager: &mut GameManager) -> i32 {
+        let mut plies = 0;
+
+        let value = loop {
+            self.position_counter += 1;
+
+            let status = game_manager.get_game_status();
+            if status != GameStatus::Playing {
+                break status_value(&status);
+            }
+
+            match game_manager.get_legal_moves().choose(&mut self.rng).cloned() {
+                Some(m) => {
+                    game_manager.do_move(&m);
+                    plies += 1;
+                }
+                // the side to move is blocked and loses
+                None => break match game_manager.get_turn() {
+                    Turn::Red => BLACKVALUE,
+                    Turn::Black => REDVALUE,
+                },
+            }
+        };
+
+        for _ in 0..plies {
+            game_manager.undo_move();
+        }
+        value
+    }
+
+    fn info(&self, start: Instant) -> SearchInfo {
+        let mut pv = Vec::new();
+        let mut node = 0;
+        while let Some(child) = self.most_visited(node) {
+            pv.push(self.nodes[child].m.clone().unwrap());
+            node = child;
+        }
+
+        SearchInfo {
+            depth: pv.len() as u32,
+            score: self.most_visited(0).map(|child| self.nodes[child].mean()).unwrap_or_default(),
+            nodes: self.position_counter,
+            time: start.elapsed(),
+            pv,
+        }
+    }
+}
+
+impl Brain for Mcts {
+    fn get_best_move(&mut self, game_manager: GameManager) -> Option<Move> {
+        self.search(game_manager, &SearchLimits::default(), &AtomicBool::new(false), &mut |_| {})
+    }
+
+    /// Runs playouts until the limits, playing the most visited move. The depth reported is
+    /// the length of the principal variation.
+    fn search(&mut self, mut game_manager: GameManager, limits: &SearchLimits, stop: &AtomicBool,
+              info: &mut dyn FnMut(SearchInfo)) -> Option<Move> {
+        self.position_counter = 0;
+        let start = Instant::now();
+
+        if game_manager.get_game_status() != GameStatus::Playing || game_manager.get_legal_moves().is_empty() {
+            return None;
+        }
+        if !self.reuse_tree(&game_manager) {
+            self.new_tree(&mut game_manager);
+        }
+
+        let time = limits.time.or(limits.clock.map(|clock| clock.get_budget()));
+        let unlimited = time.is_none() && limits.nodes.is_none() && !limits.infinite;
+
+        // a single move needs no thinking
+        let mut playouts = 0;
+        while self.nodes[0].children.len() + self.nodes[0].untried.len() > 1 {
+            let done = if unlimited {
+                playouts >= self.iterations
+            } else {
+                limits.nodes.is_some_and(|nodes| self.position_counter >= nodes)
+                    || time.is_some_and(|time| start.elapsed() >= time)
+            };
+            // at least one playout, so there is a move to report
+            if playouts > 0 && (done || stop.load(Ordering::Relaxed)) {
+                break;
+            }
+
+            self.iterate(&mut game_manager);
+            playouts += 1;
+
+            if playouts % INFO_INTERVAL == 0 {
+                info(self.info(start));
+            }
+        }
+
+        let best = match self.most_visited(0) {
+            Some(child) => self.nodes[child].m.clone(),
+            // a single legal move
+            None => game_manager.get_legal_moves().first().cloned(),
+        };
+
+        let mut last = self.info(start);
+        if last.pv.is_empty() {
+            last.pv.extend(best.clone());
+        }
+        info(last);
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Variant;
+
+    #[test]
+    fn search_stays_within_limits() {
+        let mut game_manager = GameManager::with_variant(Variant::English);
+        let mut mcts = Mcts::with_iterations(50);
+        let limits = SearchLimits { nodes: Some(2000), ..SearchLimits::default() };
+
+        let mut last = SearchInfo::default();
+        let best = mcts.search(game_manager.clone(), &limits, &AtomicBool::new(false), &mut |info| last = info).unwrap();
+
+        assert!(game_manager.get_legal_moves().contains(&best));
+        assert_eq!(last.pv.first(), Some(&best));
+        assert!(last.nodes >= 2000);
+    }
+
+    #[test]
+    fn tree_is_kept_after_moves() {
+        let mut game_manager = GameManager::with_variant(Variant::English);
+        let mut mcts = Mcts::with_iterations(300);
+
+        let best = mcts.get_best_move(game_manager.clone()).unwrap();
+        let best_node = mcts.most_visited(0).unwrap();
+        let reply_node = mcts.most_visited(best_node).unwrap();
+        let reply = mcts.nodes[reply_node].m.clone().unwrap();
+        let visits = mcts.nodes[reply_node].visits;
+
+        game_manager.do_move(&best);
+        game_manager.do_move(&reply);
+        assert!(mcts.reuse_tree(&game_manager));
+        assert_eq!(mcts.nodes[0].visits, visits);
+        assert!(mcts.nodes.iter().skip(1).all(|node| node.parent.is_some_and(|parent| parent < mcts.nodes.len())));
+    }
+
+    #[test]
+    fn tree_is_not_kept_across_variants() {
+        // Spanish and Russian start from the same position, with different moves
+        let mut mcts = Mcts::with_iterations(50);
+        mcts.get_best_move(GameManager::with_variant(Variant::Spanish)).unwrap();
+
+        assert!(mcts.reuse_tree(&GameManager::with_variant(Variant::Spanish)));
+        assert!(!mcts.reuse_tree(&GameManager::with_variant(Variant::Russian)));
+    }
+}
@@ -1,9 +1,10 @@
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use crate::comun::{GameStatus, Move, Turn};
 use crate::game_manager::GameManager;
-use crate::ia::brain::{BLACKVALUE, Brain, DRAWVALUE, REDVALUE};
+use crate::ia::brain::{BLACKVALUE, Brain, DRAWVALUE, REDVALUE, SearchInfo, SearchLimits};
 
 const MAX_ITERATIONS: u16 = 700;
 
@@ -12,10 +13,21 @@ pub struct MonteCarlo{
     position_counter: u32
 }
 
+impl Default for MonteCarlo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MonteCarlo {
     pub fn new() -> Self{
+        Self::with_iterations(MAX_ITERATIONS)
+    }
+
+    /// Random games played after each candidate move; more is stronger and slower.
+    pub fn with_iterations(iterations_for_movement: u16) -> Self {
         MonteCarlo {
-            iterations_for_movement: MAX_ITERATIONS,
+            iterations_for_movement,
             position_counter: 0
         }
     }
@@ -39,77 +51,98 @@ impl MonteCarlo {
         // get random element from moves
         let mut rng = thread_rng();
 
-        if let Some(&random_move) = moves.choose(&mut rng) {
-            game_manager.do_move(random_move);
+        if let Some(random_move) = moves.choose(&mut rng).cloned() {
+            game_manager.do_move(&random_move);
             let v = self.play_random_game(game_manager);
-            game_manager.undo_move(random_move);
+            game_manager.undo_move();
             v
         }
         else {
-            return match game_manager.get_turn() {
+            match game_manager.get_turn() {
                 Turn::Red => BLACKVALUE,
                 Turn::Black => REDVALUE
             }
         }
     }
+}
 
-    fn monte_carlo_value(&mut self, game_manager: &mut GameManager, iterations_opt: Option<u16>) -> f32 {
-        let mut iterations: u16 = MAX_ITERATIONS;
-        if let Some(i) = iterations_opt {
-            iterations = i;
-        }
-
-        let mut values: Vec<i32> = Vec::new();
-        for _ in 0..iterations {
-            values.push(self.play_random_game(game_manager));
-        }
-
-        let suma :i32 = values.iter().sum();
-
-        let average = suma as f32 / (iterations as f32);
-        println!("Average: {}", average);
+/// How often, in random games, the search reports its progress.
+const INFO_INTERVAL: u32 = 256;
 
-        average
+impl Brain for MonteCarlo {
+    fn get_best_move(&mut self, game_manager: GameManager) -> Option<Move> {
+        self.search(game_manager, &SearchLimits::default(), &AtomicBool::new(false), &mut |_| {})
     }
 
-}
-
-impl Brain for MonteCarlo {
-    fn get_best_move(&mut self, mut game_manager: GameManager) -> Move {
+    /// Plays random games after every candidate move in turn and keeps the move with the best
+    /// average result. The depth reported is the number of games played after each move.
+    fn search(&mut self, mut game_manager: GameManager, limits: &SearchLimits, stop: &AtomicBool,
+              info: &mut dyn FnMut(SearchInfo)) -> Option<Move> {
         self.position_counter = 0;
-        let moves = game_manager.get_legal_moves();
-        let moves_cloned = moves.clone();
-
-        let mut action_dict: HashMap<Move, f32> = HashMap::new();
+        let start = Instant::now();
+        let moves = game_manager.get_legal_moves().clone();
 
         let turn_value = match game_manager.get_turn() {
             Turn::Red => REDVALUE,
             Turn::Black => BLACKVALUE
         };
+        let time = limits.time.or(limits.clock.map(|clock| clock.get_budget()));
+        let unlimited = time.is_none() && limits.nodes.is_none() && !limits.infinite;
+
+        // sum of the results for the side to move and games played, per move
+        let mut results: Vec<(i32, u32)> = vec![(0, 0); moves.len()];
+        let mut games = 0;
+
+        let best = |results: &[(i32, u32)]| results.iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| average(a).partial_cmp(&average(b)).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(i, result)| (i, average(result)));
+
+        let report = |results: &[(i32, u32)], position_counter: u32, info: &mut dyn FnMut(SearchInfo)| {
+            if let Some((i, score)) = best(results) {
+                info(SearchInfo {
+                    depth: results[i].1,
+                    score,
+                    nodes: position_counter as u64,
+                    time: start.elapsed(),
+                    pv: vec![moves[i].clone()],
+                });
+            }
+        };
 
-        for m in moves_cloned.iter() {
-            game_manager.do_move(*m);
-            action_dict.insert(*m,turn_value as f32 * self.monte_carlo_value(&mut game_manager, Some(self.iterations_for_movement)));
-            game_manager.undo_move(*m);
-        }
-
-        let movement_opt = action_dict.iter()
-            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(std::cmp::Ordering::Equal))
-            .map(|(k, _v)| k.clone());
+        // a single move needs no thinking
+        while moves.len() > 1 {
+            let i = games as usize % moves.len();
+            if i == 0 {
+                let done = if unlimited {
+                    results[0].1 >= self.iterations_for_movement as u32
+                } else {
+                    limits.nodes.is_some_and(|nodes| self.position_counter as u64 >= nodes)
+                        || time.is_some_and(|time| start.elapsed() >= time)
+                };
+                if done || stop.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
 
-        println!("action_dict: {:?}", action_dict);
+            game_manager.do_move(&moves[i]);
+            let value = self.play_random_game(&mut game_manager);
+            game_manager.undo_move();
 
-        println!("Position counter: {}", self.position_counter);
+            results[i].0 += turn_value * value;
+            results[i].1 += 1;
+            games += 1;
 
-        match movement_opt {
-            Some(m) => m,
-            None => Move {
-                turn: game_manager.get_turn(),
-                from: Default::default(),
-                to: Default::default(),
-                eat: None,
-                promotion: false
+            if games % INFO_INTERVAL == 0 {
+                report(&results, self.position_counter, info);
             }
         }
+
+        report(&results, self.position_counter, info);
+        best(&results).map(|(i, _)| moves[i].clone())
     }
 }
+
+fn average(&(sum, games): &(i32, u32)) -> f32 {
+    if games == 0 { 0.0 } else { sum as f32 / games as f32 }
+}
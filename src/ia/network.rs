@@ -0,0 +1,145 @@
+//! What a policy/value network sees and answers, independent of how it is implemented.
+//!
+//! Positions are seen from the side to move, turned around for Black so that the side to
+//! move always plays up the board. Moves are actions `from * squares + to`, numbering the
+//! playable squares of that view from 0 row by row.
+
+use crate::comun::{Move, Square, Turn, BLACK_PAWN, BLACK_QUEEN, WHITE_PAWN, WHITE_QUEEN};
+use crate::game_manager::GameManager;
+
+/// Input planes: own pawns, own queens, opponent pawns and opponent queens.
+pub const INPUT_PLANES: usize = 4;
+
+pub trait Network: Send + Sync {
+    /// Board size the network was built for.
+    fn get_board_size(&self) -> usize;
+
+    /// Policy logits over every action and the expected result for the side to move, from
+    /// -1 to 1, of a position made by `encode_position`.
+    fn evaluate(&self, input: &[f32]) -> (Vec<f32>, f32);
+}
+
+/// Actions on a board of `board_size`: every pair of playable squares.
+pub fn action_count(board_size: usize) -> usize {
+    let squares = board_size * board_size / 2;
+    squares * squares
+}
+
+/// `square` as seen by `turn`.
+fn oriented(square: Square, turn: Turn, board_size: usize) -> Square {
+    match turn {
+        Turn::Red => square,
+        Turn::Black => Square { row: board_size - 1 - square.row, col: board_size - 1 - square.col },
+    }
+}
+
+fn square_index(square: Square, board_size: usize) -> usize {
+    square.row * (board_size / 2) + square.col / 2
+}
+
+/// Action of `m` for the side playing it.
+pub fn move_action(m: &Move, board_size: usize) -> usize {
+    let squares = board_size * board_size / 2;
+    let from = square_index(oriented(m.from, m.turn, board_size), board_size);
+    let to = square_index(oriented(m.to, m.turn, board_size), board_size);
+    from * squares + to
+}
+
+/// The board from the side to move, as `INPUT_PLANES` planes of `board_size` x `board_size`.
+pub fn encode_position(game_manager: &GameManager) -> Vec<f32> {
+    let size = game_manager.get_board_size();
+    let turn = game_manager.get_turn();
+    let mut input = vec![0.0; INPUT_PLANES * size * size];
+
+    for (row, pieces) in game_manager.get_board().iter().enumerate() {
+        for (col, &piece) in pieces.iter().enumerate() {
+            let (red_plane, queen) = match piece {
+                WHITE_PAWN => (true, false),
+                WHITE_QUEEN => (true, true),
+                BLACK_PAWN => (false, false),
+                BLACK_QUEEN => (false, true),
+                _ => continue,
+            };
+            let own = red_plane == (turn == Turn::Red);
+            let plane = if own { 0 } else { 2 } + queen as usize;
+            let square = oriented(Square { row, col }, turn, size);
+            input[(plane * size + square.row) * size + square.col] = 1.0;
+        }
+    }
+
+    input
+}
+
+/// Prior probability of every legal move and the value for the side to move. Moves that
+/// share an action, different capture paths between the same squares, share its probability.
+pub fn evaluate_moves(network: &dyn Network, game_manager: &mut GameManager) -> (Vec<(Move, f32)>, f32) {
+    let size = game_manager.get_board_size();
+    let (logits, value) = network.evaluate(&encode_position(game_manager));
+    let moves = game_manager.get_legal_moves().clone();
+
+    let actions: Vec<usize> = moves.iter().map(|m| move_action(m, size)).collect();
+    let max = actions.iter().map(|&action| logits[action]).fold(f32::NEG_INFINITY, f32::max);
+    let weights: Vec<f32> = actions.iter()
+        .map(|&action| (logits[action] - max).exp() / actions.iter().filter(|&&other| other == action).count() as f32)
+        .collect();
+    let total: f32 = weights.iter().sum();
+
+    let priors = moves.into_iter().zip(weights).map(|(m, weight)| (m, weight / total)).collect();
+    (priors, value)
+}
+
+/// A network that knows nothing: every move equally likely and every position even.
+pub struct UniformNetwork {
+    board_size: usize,
+}
+
+impl UniformNetwork {
+    pub fn new(board_size: usize) -> UniformNetwork {
+        UniformNetwork { board_size }
+    }
+}
+
+impl Network for UniformNetwork {
+    fn get_board_size(&self) -> usize { self.board_size }
+
+    fn evaluate(&self, _input: &[f32]) -> (Vec<f32>, f32) {
+        (vec![0.0; action_count(self.board_size)], 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Variant;
+
+    #[test]
+    fn both_sides_see_themselves_moving_up() {
+        let mut red = GameManager::from_fen(Variant::English, "W:W22:B11").unwrap();
+        let mut black = GameManager::from_fen(Variant::English, "B:W22:B11").unwrap();
+
+        // each side's own pawn is on the first plane, mirrored through the centre
+        let red_input = encode_position(&red);
+        let black_input = encode_position(&black);
+        assert_eq!(red_input.iter().sum::<f32>(), 2.0);
+        assert_eq!(red_input[..64].iter().position(|&x| x == 1.0), Some(2 * 8 + 2));
+        assert_eq!(black_input[..64].iter().position(|&x| x == 1.0), Some(2 * 8 + 2));
+
+        for game_manager in [&mut red, &mut black] {
+            let moves = game_manager.get_legal_moves().clone();
+            let actions: Vec<usize> = moves.iter().map(|m| move_action(m, 8)).collect();
+            assert!(actions.iter().all(|&action| action < action_count(8)));
+            // forward is up the board for both
+            assert!(actions.iter().all(|&action| action % 32 > action / 32));
+        }
+    }
+
+    #[test]
+    fn uniform_priors_add_up_to_one() {
+        let mut game_manager = GameManager::new();
+        let (priors, value) = evaluate_moves(&UniformNetwork::new(8), &mut game_manager);
+
+        assert_eq!(priors.len(), game_manager.get_legal_moves().len());
+        assert!((priors.iter().map(|(_, prior)| prior).sum::<f32>() - 1.0).abs() < 1e-6);
+        assert_eq!(value, 0.0);
+    }
+}
@@ -0,0 +1,348 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Gamma};
+use crate::comun::{GameStatus, Move, Turn};
+use crate::game_manager::GameManager;
+use crate::ia::brain::{BLACKVALUE, Brain, DRAWVALUE, REDVALUE, SearchInfo, SearchLimits};
+use crate::ia::network::{evaluate_moves, Network};
+
+/// How often, in simulations, the search reports its progress.
+const INFO_INTERVAL: u32 = 64;
+
+/// Settings of the search. The defaults play the strongest move found; `self_play` explores.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PuctConfig {
+    /// Simulations per move when the search has no limits.
+    pub simulations: u32,
+    /// Weight of the network priors against the results found, `c_puct`.
+    pub exploration: f32,
+    /// Concentration of the Dirichlet noise mixed into the root priors.
+    pub dirichlet_alpha: f32,
+    /// Share of the noise in the root priors; 0 leaves them alone.
+    pub noise_fraction: f32,
+    /// Moves are picked with probability visits^(1 / temperature); 0 picks the most visited.
+    pub temperature: f32,
+    /// Plies of the game played with `temperature`, the most visited move after them.
+    pub temperature_plies: usize,
+}
+
+impl Default for PuctConfig {
+    fn default() -> Self {
+        PuctConfig {
+            simulations: 200,
+            exploration: 1.5,
+            dirichlet_alpha: 0.3,
+            noise_fraction: 0.0,
+            temperature: 0.0,
+            temperature_plies: 0,
+        }
+    }
+}
+
+impl PuctConfig {
+    /// Root noise and varied openings, for games played to learn from.
+    pub fn self_play() -> PuctConfig {
+        PuctConfig { noise_fraction: 0.25, temperature: 1.0, temperature_plies: 20, ..PuctConfig::default() }
+    }
+}
+
+struct Node {
+    /// Move leading here from the parent, `None` at the root.
+    m: Option<Move>,
+    /// Side that played `m`.
+    turn: Turn,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    prior: f32,
+    visits: u32,
+    /// Sum of the values for the side that played `m`.
+    value: f32,
+    expanded: bool,
+}
+
+impl Node {
+    fn new(m: Option<Move>, turn: Turn, parent: Option<usize>, prior: f32) -> Node {
+        Node { m, turn, parent, children: Vec::new(), prior, visits: 0, value: 0.0, expanded: false }
+    }
+
+    fn mean(&self) -> f32 {
+        if self.visits == 0 { 0.0 } else { self.value / self.visits as f32 }
+    }
+}
+
+/// AlphaZero search: a tree grown with PUCT, where the network gives the priors of the moves
+/// and the value of the positions reached instead of random games.
+pub struct Puct {
+    network: Arc<dyn Network>,
+    config: PuctConfig,
+    nodes: Vec<Node>,
+    rng: StdRng,
+    evaluations: u64,
+}
+
+/// `value` for `turn`, `value` being for Red.
+fn value_for(value: f32, turn: Turn) -> f32 {
+    match turn {
+        Turn::Red => value * REDVALUE as f32,
+        Turn::Black => value * BLACKVALUE as f32,
+    }
+}
+
+impl Puct {
+    pub fn new(network: Arc<dyn Network>, config: PuctConfig) -> Self {
+        Puct { network, config, nodes: Vec::new(), rng: StdRng::from_entropy(), evaluations: 0 }
+    }
+
+    /// Draws the noise and the moves played with the temperature from `seed`, so the same
+    /// games come out every time.
+    pub fn with_seed(network: Arc<dyn Network>, config: PuctConfig, seed: u64) -> Self {
+        Puct { rng: StdRng::seed_from_u64(seed), ..Puct::new(network, config) }
+    }
+
+    /// Visits of every root move in the last search, the policy it found.
+    pub fn get_root_visits(&self) -> Vec<(Move, u32)> {
+        let Some(root) = self.nodes.first() else {
+            return Vec::new();
+        };
+        root.children.iter()
+            .map(|&child| (self.nodes[child].m.clone().unwrap(), self.nodes[child].visits))
+            .collect()
+    }
+
+    /// Adds the legal moves of the position under `node` with their priors, returning the value
+    /// of the position for Red.
+    fn expand(&mut self, node: usize, game_manager: &mut GameManager) -> f32 {
+        let status = game_manager.get_game_status();
+        if status != GameStatus::Playing {
+            self.nodes[node].expanded = true;
+            return match status.get_winner() {
+                Some(Turn::Red) => REDVALUE as f32,
+                Some(Turn::Black) => BLACKVALUE as f32,
+                None => DRAWVALUE as f32,
+            };
+        }
+
+        let turn = game_manager.get_turn();
+        let (priors, value) = evaluate_moves(self.network.as_ref(), game_manager);
+        self.evaluations += 1;
+
+        for (m, prior) in priors {
+            let child = self.nodes.len();
+            self.nodes.push(Node::new(Some(m), turn, Some(node), prior));
+            self.nodes[node].children.push(child);
+        }
+        self.nodes[node].expanded = true;
+
+        // a blocked side has lost whatever the network says
+        if self.nodes[node].children.is_empty() {
+            return value_for(-1.0, turn);
+        }
+        value_for(value, turn)
+    }
+
+    /// Mixes Dirichlet noise into the priors of the root moves.
+    fn add_noise(&mut self) {
+        let Ok(gamma) = Gamma::new(self.config.dirichlet_alpha, 1.0) else {
+            return;
+        };
+        let noise: Vec<f32> = self.nodes[0].children.iter().map(|_| gamma.sample(&mut self.rng)).collect();
+        let total: f32 = noise.iter().sum();
+        if total <= 0.0 {
+            return;
+        }
+
+        let fraction = self.config.noise_fraction;
+        for (i, child) in self.nodes[0].children.clone().into_iter().enumerate() {
+            let prior = &mut self.nodes[child].prior;
+            *prior = (1.0 - fraction) * *prior + fraction * noise[i] / total;
+        }
+    }
+
+    /// The child of `node` with the best PUCT score.
+    fn select_child(&self, node: usize) -> usize {
+        let sqrt_visits = (self.nodes[node].visits as f32).sqrt();
+        let puct = |child: usize| {
+            let child = &self.nodes[child];
+            child.mean() + self.config.exploration * child.prior * sqrt_visits / (1 + child.visits) as f32
+        };
+
+        *self.nodes[node].children.iter()
+            .max_by(|&&a, &&b| puct(a).partial_cmp(&puct(b)).unwrap_or(std::cmp::Ordering::Equal))
+            .unwrap()
+    }
+
+    fn most_visited(&self, node: usize) -> Option<usize> {
+        self.nodes[node].children.iter().copied().max_by_key(|&child| self.nodes[child].visits)
+    }
+
+    /// One simulation: down the tree with PUCT to a new position, which the network evaluates.
+    fn simulate(&mut self, game_manager: &mut GameManager) {
+        let mut node = 0;
+        let mut depth = 0;
+
+        while self.nodes[node].expanded && !self.nodes[node].children.is_empty() {
+            node = self.select_child(node);
+            let m = self.nodes[node].m.clone().unwrap();
+            game_manager.do_move(&m);
+            depth += 1;
+        }
+
+        // finished games are scored again, new positions evaluated by the network
+        let value = self.expand(node, game_manager);
+
+        let mut current = Some(node);
+        while let Some(i) = current {
+            let node = &mut self.nodes[i];
+            node.visits += 1;
+            node.value += value_for(value, node.turn);
+            current = node.parent;
+        }
+
+        for _ in 0..depth {
+            game_manager.undo_move();
+        }
+    }
+
+    /// The move to play from the root visits, with the temperature early in the game.
+    fn choose_move(&mut self, plies: usize) -> Option<usize> {
+        let temperature = self.config.temperature;
+        if temperature <= 0.0 || plies >= self.config.temperature_plies {
+            return self.most_visited(0);
+        }
+
+        let children = &self.nodes[0].children;
+        let weights: Vec<f64> = children.iter()
+            .map(|&child| (self.nodes[child].visits as f64).powf(1.0 / temperature as f64))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return self.most_visited(0);
+        }
+
+        let mut pick = self.rng.gen::<f64>() * total;
+        for (i, weight) in weights.iter().enumerate() {
+            if pick < *weight {
+                return Some(children[i]);
+            }
+            pick -= weight;
+        }
+        children.last().copied()
+    }
+
+    fn info(&self, start: Instant) -> SearchInfo {
+        let mut pv = Vec::new();
+        let mut node = 0;
+        while let Some(child) = self.most_visited(node).filter(|&child| self.nodes[child].visits > 0) {
+            pv.push(self.nodes[child].m.clone().unwrap());
+            node = child;
+        }
+
+        SearchInfo {
+            depth: pv.len() as u32,
+            score: self.most_visited(0).map(|child| self.nodes[child].mean()).unwrap_or_default(),
+            nodes: self.evaluations,
+            time: start.elapsed(),
+            pv,
+        }
+    }
+}
+
+impl Brain for Puct {
+    fn get_best_move(&mut self, game_manager: GameManager) -> Option<Move> {
+        self.search(game_manager, &SearchLimits::default(), &AtomicBool::new(false), &mut |_| {})
+    }
+
+    /// Runs simulations until the limits. The nodes reported are network evaluations and the
+    /// depth the length of the principal variation.
+    fn search(&mut self, mut game_manager: GameManager, limits: &SearchLimits, stop: &AtomicBool,
+              info: &mut dyn FnMut(SearchInfo)) -> Option<Move> {
+        let start = Instant::now();
+        self.evaluations = 0;
+
+        if game_manager.get_game_status() != GameStatus::Playing || game_manager.get_legal_moves().is_empty() {
+            return None;
+        }
+
+        self.nodes = vec![Node::new(None, game_manager.get_turn().opposite(), None, 1.0)];
+        self.expand(0, &mut game_manager);
+        if self.config.noise_fraction > 0.0 {
+            self.add_noise();
+        }
+
+        let time = limits.time.or(limits.clock.map(|clock| clock.get_budget()));
+        let unlimited = time.is_none() && limits.nodes.is_none() && !limits.infinite;
+
+        // a single move needs no thinking
+        let mut simulations = 0;
+        while self.nodes[0].children.len() > 1 {
+            let done = if unlimited {
+                simulations >= self.config.simulations
+            } else {
+                limits.nodes.is_some_and(|nodes| self.evaluations >= nodes)
+                    || time.is_some_and(|time| start.elapsed() >= time)
+            };
+            // at least one simulation, so there is a move to report
+            if simulations > 0 && (done || stop.load(Ordering::Relaxed)) {
+                break;
+            }
+
+            self.simulate(&mut game_manager);
+            simulations += 1;
+
+            if simulations % INFO_INTERVAL == 0 {
+                info(self.info(start));
+            }
+        }
+
+        let plies = game_manager.get_played_moves().len();
+        let best = match self.choose_move(plies) {
+            Some(child) => self.nodes[child].m.clone(),
+            None => game_manager.get_legal_moves().first().cloned(),
+        };
+
+        let mut last = self.info(start);
+        if last.pv.is_empty() {
+            last.pv.extend(best.clone());
+        }
+        info(last);
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ia::network::UniformNetwork;
+    use crate::rules::Variant;
+
+    fn uniform(config: PuctConfig) -> Puct {
+        Puct::new(Arc::new(UniformNetwork::new(8)), config)
+    }
+
+    #[test]
+    fn avoids_losing_the_last_piece() {
+        // 18-22 lets 25 take the last black piece, 18-23 is safe
+        let game_manager = GameManager::from_fen(Variant::English, "B:W25:B18").unwrap();
+        let mut puct = uniform(PuctConfig { simulations: 100, ..PuctConfig::default() });
+
+        let best = puct.get_best_move(game_manager.clone()).unwrap();
+        assert_eq!(game_manager.clone().get_move_text(&best), "18-23");
+    }
+
+    #[test]
+    fn self_play_moves_are_legal() {
+        let mut game_manager = GameManager::with_variant(Variant::English);
+        let mut puct = uniform(PuctConfig { simulations: 50, ..PuctConfig::self_play() });
+
+        for _ in 0..4 {
+            let m = puct.get_best_move(game_manager.clone()).unwrap();
+            // the noise keeps the root priors a distribution
+            let priors: f32 = puct.nodes[0].children.iter().map(|&child| puct.nodes[child].prior).sum();
+            assert!((priors - 1.0).abs() < 1e-4);
+            assert_eq!(game_manager.try_move(&m), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Residual convolutional policy/value network, run on the CPU with candle.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use candle_core::{DType, Device, Module, Result, Tensor};
+use candle_nn::{conv2d, linear, Conv2d, Conv2dConfig, Linear, VarBuilder, VarMap};
+
+use crate::ia::network::{action_count, Network, INPUT_PLANES};
+
+/// Name of the tensor holding the shape of the network in a checkpoint.
+const CONFIG_TENSOR: &str = "config";
+/// Width of the hidden layer of the value head.
+const VALUE_HIDDEN: usize = 64;
+
+/// Shape of a `ResNet`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResNetConfig {
+    pub board_size: usize,
+    /// Filters of every convolution in the tower.
+    pub channels: usize,
+    pub blocks: usize,
+}
+
+impl ResNetConfig {
+    /// A small network that runs fast enough on the CPU.
+    pub fn small(board_size: usize) -> ResNetConfig {
+        ResNetConfig { board_size, channels: 32, blocks: 4 }
+    }
+}
+
+struct ResidualBlock {
+    first: Conv2d,
+    second: Conv2d,
+}
+
+impl ResidualBlock {
+    fn new(channels: usize, vb: VarBuilder) -> Result<ResidualBlock> {
+        let config = Conv2dConfig { padding: 1, ..Default::default() };
+        Ok(ResidualBlock {
+            first: conv2d(channels, channels, 3, config, vb.pp("first"))?,
+            second: conv2d(channels, channels, 3, config, vb.pp("second"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let ys = self.first.forward(xs)?.relu()?;
+        (self.second.forward(&ys)? + xs)?.relu()
+    }
+}
+
+/// A tower of residual blocks with a policy head over every from x to action and a value head.
+pub struct ResNet {
+    config: ResNetConfig,
+    /// The trainable weights, shared with the layers.
+    varmap: VarMap,
+    stem: Conv2d,
+    blocks: Vec<ResidualBlock>,
+    policy_conv: Conv2d,
+    policy: Linear,
+    value_conv: Conv2d,
+    value_hidden: Linear,
+    value: Linear,
+}
+
+impl ResNet {
+    /// A network with random weights.
+    pub fn new(config: ResNetConfig) -> Result<ResNet> {
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let squares = config.board_size * config.board_size;
+        let padded = Conv2dConfig { padding: 1, ..Default::default() };
+
+        Ok(ResNet {
+            config,
+            stem: conv2d(INPUT_PLANES, config.channels, 3, padded, vb.pp("stem"))?,
+            blocks: (0..config.blocks)
+                .map(|i| ResidualBlock::new(config.channels, vb.pp(format!("block{}", i))))
+                .collect::<Result<_>>()?,
+            policy_conv: conv2d(config.channels, 2, 1, Default::default(), vb.pp("policy_conv"))?,
+            policy: linear(2 * squares, action_count(config.board_size), vb.pp("policy"))?,
+            value_conv: conv2d(config.channels, 1, 1, Default::default(), vb.pp("value_conv"))?,
+            value_hidden: linear(squares, VALUE_HIDDEN, vb.pp("value_hidden"))?,
+            value: linear(VALUE_HIDDEN, 1, vb.pp("value"))?,
+            varmap,
+        })
+    }
+
+    /// Reads a network written by `save`.
+    pub fn load(path: impl AsRef<Path>) -> Result<ResNet> {
+        let mut tensors = candle_core::safetensors::load(path, &Device::Cpu)?;
+        let shape = tensors.remove(CONFIG_TENSOR)
+            .ok_or_else(|| candle_core::Error::Msg("not a network checkpoint".to_string()))?
+            .to_vec1::<u32>()?;
+        let [board_size, channels, blocks] = shape[..] else {
+            return Err(candle_core::Error::Msg("bad network shape".to_string()));
+        };
+
+        let mut network = ResNet::new(ResNetConfig {
+            board_size: board_size as usize,
+            channels: channels as usize,
+            blocks: blocks as usize,
+        })?;
+        for (name, tensor) in tensors {
+            network.varmap.set_one(name, tensor)?;
+        }
+        Ok(network)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let config = self.config;
+        let shape = [config.board_size as u32, config.channels as u32, config.blocks as u32];
+
+        let mut tensors: HashMap<String, Tensor> = self.varmap.data().lock().unwrap()
+            .iter()
+            .map(|(name, var)| (name.clone(), var.as_tensor().clone()))
+            .collect();
+        tensors.insert(CONFIG_TENSOR.to_string(), Tensor::new(&shape[..], &Device::Cpu)?);
+        candle_core::safetensors::save(&tensors, path)
+    }
+
+    pub fn get_config(&self) -> ResNetConfig { self.config }
+
+    /// The weights, for an optimizer to train.
+    pub fn get_varmap(&self) -> &VarMap { &self.varmap }
+
+    /// Policy logits `[batch, actions]` and values `[batch]` of positions `[batch, planes, size, size]`.
+    pub fn forward(&self, xs: &Tensor) -> Result<(Tensor, Tensor)> {
+        let mut xs = self.stem.forward(xs)?.relu()?;
+        for block in &self.blocks {
+            xs = block.forward(&xs)?;
+        }
+
+        let policy = self.policy_conv.forward(&xs)?.relu()?.flatten_from(1)?;
+        let policy = self.policy.forward(&policy)?;
+
+        let value = self.value_conv.forward(&xs)?.relu()?.flatten_from(1)?;
+        let value = self.value_hidden.forward(&value)?.relu()?;
+        let value = self.value.forward(&value)?.tanh()?.flatten_all()?;
+
+        Ok((policy, value))
+    }
+}
+
+impl Network for ResNet {
+    fn get_board_size(&self) -> usize { self.config.board_size }
+
+    fn evaluate(&self, input: &[f32]) -> (Vec<f32>, f32) {
+        let size = self.config.board_size;
+        let evaluate = || -> Result<(Vec<f32>, f32)> {
+            let xs = Tensor::from_slice(input, (1, INPUT_PLANES, size, size), &Device::Cpu)?;
+            let (policy, value) = self.forward(&xs)?;
+            Ok((policy.flatten_all()?.to_vec1()?, value.to_vec1::<f32>()?[0]))
+        };
+
+        // the shapes are fixed by the config, so only a bug can make this fail
+        evaluate().expect("network evaluation failed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_manager::GameManager;
+    use crate::ia::network::encode_position;
+
+    #[test]
+    fn saved_networks_answer_the_same() {
+        let network = ResNet::new(ResNetConfig { board_size: 8, channels: 8, blocks: 1 }).unwrap();
+        let input = encode_position(&GameManager::new());
+        let (policy, value) = network.evaluate(&input);
+        assert_eq!(policy.len(), action_count(8));
+        assert!((-1.0..=1.0).contains(&value));
+
+        let path = std::env::temp_dir().join(format!("dqz-resnet-{}.safetensors", std::process::id()));
+        network.save(&path).unwrap();
+        let loaded = ResNet::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.get_config(), network.get_config());
+        assert_eq!(loaded.evaluate(&input), (policy, value));
+    }
+}
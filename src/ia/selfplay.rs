@@ -0,0 +1,321 @@
+//! Games the PUCT search plays against itself, recorded to train the network.
+//!
+//! Every position is kept as the network sees it, with the visits the search gave each move
+//! and the result of the game. Samples are written to a compact binary file:
+//!
+//! ```text
+//! "DQZS" version:u8 board_size:u8
+//! per sample: turn:u8 outcome:i8 planes:bits moves:u16 (action:u16 visits:u16)*
+//! ```
+//!
+//! Mirrors of the positions can be added. A plain left-right mirror would move the pieces to
+//! the unplayable squares, so it is shifted one column over: the first column falls off and an
+//! empty one appears on the other side. Only positions far enough from the edges that the moves
+//! stay the same are mirrored.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::comun::{GameStatus, Turn};
+use crate::game_manager::GameManager;
+use crate::ia::brain::{Brain, BLACKVALUE, DRAWVALUE, REDVALUE};
+use crate::ia::network::{encode_position, move_action, Network, INPUT_PLANES};
+use crate::ia::puct::{Puct, PuctConfig};
+use crate::rules::Variant;
+
+const MAGIC: &[u8; 4] = b"DQZS";
+const VERSION: u8 = 1;
+
+/// One position of a self-play game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// `encode_position` of the position, one byte per value.
+    pub planes: Vec<u8>,
+    /// Search visits of every legal move, by action.
+    pub visits: Vec<(u16, u16)>,
+    /// Side to move.
+    pub turn: Turn,
+    /// Result of the game: `REDVALUE`, `BLACKVALUE` or `DRAWVALUE`.
+    pub outcome: i32,
+}
+
+impl Sample {
+    /// Result of the game for the side to move, from -1 to 1.
+    pub fn get_value(&self) -> f32 {
+        match self.turn {
+            Turn::Red => (self.outcome * REDVALUE) as f32,
+            Turn::Black => (self.outcome * BLACKVALUE) as f32,
+        }
+    }
+
+    /// The same position seen in a mirror shifted one column, on a board of `board_size`.
+    /// `None` when a piece stands on the columns the shift changes the edges of, or a move
+    /// goes to the first column.
+    pub fn mirrored(&self, board_size: usize) -> Option<Sample> {
+        let mut planes = vec![0; self.planes.len()];
+        for (i, &value) in self.planes.iter().enumerate() {
+            let (rest, col) = (i / board_size, i % board_size);
+            if value != 0 {
+                if col < 2 || col == board_size - 1 {
+                    return None;
+                }
+                planes[rest * board_size + board_size - col] = value;
+            }
+        }
+
+        let visits = self.visits.iter()
+            .map(|&(action, visits)| Some((mirror_action(action as usize, board_size)? as u16, visits)))
+            .collect::<Option<_>>()?;
+
+        Some(Sample { planes, visits, ..self.clone() })
+    }
+}
+
+/// `action` seen in the shifted mirror, `None` when it uses the first column. Playable squares
+/// are numbered by row and by column pairs, so a square keeps its row and column `col` goes
+/// to `board_size - col`, which has the same colour.
+fn mirror_action(action: usize, board_size: usize) -> Option<usize> {
+    let squares = board_size * board_size / 2;
+    let mirror = |index: usize| {
+        let (row, pair) = (index / (board_size / 2), index % (board_size / 2));
+        let col = pair * 2 + row % 2;
+        (col > 0).then(|| row * (board_size / 2) + (board_size - col) / 2)
+    };
+    Some(mirror(action / squares)? * squares + mirror(action % squares)?)
+}
+
+/// How the games are played.
+#[derive(Clone, Debug)]
+pub struct SelfPlayConfig {
+    pub variant: Variant,
+    pub games: usize,
+    /// Games played at the same time.
+    pub threads: usize,
+    /// Game `i` is played with seed `seed + i`, whatever the threads.
+    pub seed: u64,
+    pub puct: PuctConfig,
+    /// Whether to add the mirror of the positions that have one.
+    pub mirror: bool,
+}
+
+impl SelfPlayConfig {
+    pub fn new(variant: Variant, games: usize) -> SelfPlayConfig {
+        SelfPlayConfig {
+            variant,
+            games,
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            seed: 0,
+            puct: PuctConfig::self_play(),
+            mirror: true,
+        }
+    }
+}
+
+/// Plays one game from `seed`, returning its positions and how it ended.
+pub fn play_game(network: Arc<dyn Network>, variant: Variant, config: PuctConfig, seed: u64) -> (Vec<Sample>, GameStatus) {
+    let mut game_manager = GameManager::with_variant(variant);
+    let size = game_manager.get_board_size();
+    let mut puct = Puct::with_seed(network, config, seed);
+    let mut samples = Vec::new();
+
+    while game_manager.get_game_status() == GameStatus::Playing {
+        let Some(m) = puct.get_best_move(game_manager.clone()) else {
+            break;
+        };
+
+        let mut visits: Vec<(u16, u16)> = puct.get_root_visits().iter()
+            .filter(|(_, visits)| *visits > 0)
+            .map(|(m, visits)| (move_action(m, size) as u16, (*visits).min(u16::MAX as u32) as u16))
+            .collect();
+        if visits.is_empty() {
+            // a forced move, played without searching
+            visits.push((move_action(&m, size) as u16, 1));
+        }
+
+        samples.push(Sample {
+            planes: encode_position(&game_manager).iter().map(|&value| value as u8).collect(),
+            visits,
+            turn: game_manager.get_turn(),
+            outcome: DRAWVALUE,
+        });
+        game_manager.do_move(&m);
+    }
+
+    let status = game_manager.get_game_status();
+    let outcome = match status.get_winner() {
+        Some(Turn::Red) => REDVALUE,
+        Some(Turn::Black) => BLACKVALUE,
+        None => DRAWVALUE,
+    };
+    for sample in samples.iter_mut() {
+        sample.outcome = outcome;
+    }
+    (samples, status)
+}
+
+/// Plays `config.games` games on `config.threads` threads, calling `on_game` with the number
+/// and the result of each one as it ends. The samples come back in game order.
+pub fn generate(network: Arc<dyn Network>, config: &SelfPlayConfig,
+                on_game: impl Fn(usize, &GameStatus, usize) + Sync) -> Vec<Sample> {
+    let size = config.variant.rules().board_size;
+    let next = AtomicUsize::new(0);
+    let games: Mutex<Vec<Option<Vec<Sample>>>> = Mutex::new(vec![None; config.games]);
+
+    thread::scope(|scope| {
+        for _ in 0..config.threads.max(1) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= config.games {
+                    break;
+                }
+
+                let (mut samples, status) = play_game(network.clone(), config.variant, config.puct, config.seed + i as u64);
+                on_game(i, &status, samples.len());
+                if config.mirror {
+                    let mirrored: Vec<Sample> = samples.iter().filter_map(|sample| sample.mirrored(size)).collect();
+                    samples.extend(mirrored);
+                }
+                games.lock().unwrap()[i] = Some(samples);
+            });
+        }
+    });
+
+    games.into_inner().unwrap().into_iter().flatten().flatten().collect()
+}
+
+pub fn write_samples(writer: &mut impl Write, board_size: usize, samples: &[Sample]) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION, board_size as u8])?;
+
+    for sample in samples {
+        let turn = if sample.turn == Turn::Red { 0 } else { 1 };
+        writer.write_all(&[turn, sample.outcome as i8 as u8])?;
+
+        let mut bits = vec![0u8; sample.planes.len().div_ceil(8)];
+        for (i, &value) in sample.planes.iter().enumerate() {
+            if value != 0 {
+                bits[i / 8] |= 1 << (i % 8);
+            }
+        }
+        writer.write_all(&bits)?;
+
+        writer.write_all(&(sample.visits.len() as u16).to_le_bytes())?;
+        for &(action, visits) in &sample.visits {
+            writer.write_all(&action.to_le_bytes())?;
+            writer.write_all(&visits.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+/// Reads a file written by `write_samples`, returning the board size and the samples.
+pub fn read_samples(reader: &mut impl Read) -> io::Result<(usize, Vec<Sample>)> {
+    let mut header = [0; 6];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != MAGIC || header[4] != VERSION {
+        return Err(invalid("not a self-play file"));
+    }
+    let board_size = header[5] as usize;
+    let plane_values = INPUT_PLANES * board_size * board_size;
+
+    let mut samples = Vec::new();
+    loop {
+        let mut head = [0; 2];
+        match reader.read_exact(&mut head) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let turn = match head[0] {
+            0 => Turn::Red,
+            1 => Turn::Black,
+            _ => return Err(invalid("bad side to move")),
+        };
+
+        let mut bits = vec![0; plane_values.div_ceil(8)];
+        reader.read_exact(&mut bits)?;
+        let planes = (0..plane_values).map(|i| (bits[i / 8] >> (i % 8)) & 1).collect();
+
+        let moves = read_u16(reader)?;
+        let visits = (0..moves)
+            .map(|_| Ok((read_u16(reader)?, read_u16(reader)?)))
+            .collect::<io::Result<_>>()?;
+
+        samples.push(Sample { planes, visits, turn, outcome: head[1] as i8 as i32 });
+    }
+
+    Ok((board_size, samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ia::network::{action_count, UniformNetwork};
+
+    fn quick_config(threads: usize) -> SelfPlayConfig {
+        SelfPlayConfig {
+            threads,
+            seed: 7,
+            puct: PuctConfig { simulations: 4, ..PuctConfig::self_play() },
+            ..SelfPlayConfig::new(Variant::English, 3)
+        }
+    }
+
+    #[test]
+    fn games_depend_only_on_the_seed() {
+        let network: Arc<dyn Network> = Arc::new(UniformNetwork::new(8));
+        let one_thread = generate(network.clone(), &quick_config(1), |_, _, _| {});
+        let three_threads = generate(network, &quick_config(3), |_, _, _| {});
+
+        assert!(!one_thread.is_empty());
+        assert_eq!(one_thread, three_threads);
+        assert!(one_thread.iter().all(|sample| sample.planes.len() == INPUT_PLANES * 64));
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        let network: Arc<dyn Network> = Arc::new(UniformNetwork::new(8));
+        let (samples, status) = play_game(network, Variant::English, PuctConfig { simulations: 4, ..PuctConfig::default() }, 1);
+        assert_ne!(status, GameStatus::Playing);
+
+        let mut bytes = Vec::new();
+        write_samples(&mut bytes, 8, &samples).unwrap();
+        assert_eq!(read_samples(&mut bytes.as_slice()).unwrap(), (8, samples.clone()));
+    }
+
+    #[test]
+    fn mirrors_keep_pieces_playable() {
+        let mut game_manager = GameManager::from_fen(Variant::English, "B:W18,19,22:B10,11,14").unwrap();
+        let sample = Sample {
+            planes: encode_position(&game_manager).iter().map(|&value| value as u8).collect(),
+            visits: game_manager.get_legal_moves().iter().map(|m| (move_action(m, 8) as u16, 1)).collect(),
+            turn: Turn::Black,
+            outcome: DRAWVALUE,
+        };
+
+        let mirrored = sample.mirrored(8).unwrap();
+        assert_eq!(mirrored.mirrored(8), Some(sample.clone()));
+        assert!(mirrored.visits.iter().all(|&(action, _)| (action as usize) < action_count(8)));
+        for (i, _) in mirrored.planes.iter().enumerate().filter(|&(_, &value)| value != 0) {
+            let (row, col) = ((i / 8) % 8, i % 8);
+            assert!((row + col).is_multiple_of(2), "piece on {} {}", row, col);
+        }
+
+        // a piece on the first column falls off the board
+        let game_manager = GameManager::from_fen(Variant::English, "B:W18,19,21:B10,11,14").unwrap();
+        let edge = Sample { planes: encode_position(&game_manager).iter().map(|&value| value as u8).collect(), ..sample };
+        assert_eq!(edge.mirrored(8), None);
+    }
+}
@@ -0,0 +1,150 @@
+//! Trains a `ResNet` on the CPU from self-play samples.
+//!
+//! The policy head learns the visits the search gave the moves, with cross-entropy, and the
+//! value head the results of the games, with the mean squared error.
+
+use candle_core::{Device, Result, Tensor, D};
+use candle_nn::{loss, ops, AdamW, Optimizer, ParamsAdamW};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::ia::network::{action_count, INPUT_PLANES};
+use crate::ia::resnet::ResNet;
+use crate::ia::selfplay::Sample;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrainConfig {
+    /// Passes over the samples.
+    pub epochs: usize,
+    pub batch_size: usize,
+    pub learning_rate: f64,
+    pub weight_decay: f64,
+    /// Seed of the order the samples are seen in.
+    pub seed: u64,
+}
+
+impl Default for TrainConfig {
+    fn default() -> Self {
+        TrainConfig { epochs: 10, batch_size: 256, learning_rate: 1e-3, weight_decay: 1e-4, seed: 0 }
+    }
+}
+
+/// Mean losses of one epoch.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EpochLosses {
+    /// From 1.
+    pub epoch: usize,
+    pub policy: f32,
+    pub value: f32,
+}
+
+impl EpochLosses {
+    pub fn get_total(&self) -> f32 {
+        self.policy + self.value
+    }
+}
+
+/// Inputs, visit distributions and values of `samples` as tensors.
+fn batch_tensors(samples: &[&Sample], board_size: usize) -> Result<(Tensor, Tensor, Tensor)> {
+    let actions = action_count(board_size);
+    let mut inputs = Vec::with_capacity(samples.len() * INPUT_PLANES * board_size * board_size);
+    let mut policies = vec![0f32; samples.len() * actions];
+    let mut values = Vec::with_capacity(samples.len());
+
+    for (i, sample) in samples.iter().enumerate() {
+        inputs.extend(sample.planes.iter().map(|&value| value as f32));
+
+        let total: u32 = sample.visits.iter().map(|&(_, visits)| visits as u32).sum();
+        for &(action, visits) in &sample.visits {
+            policies[i * actions + action as usize] += visits as f32 / total.max(1) as f32;
+        }
+        values.push(sample.get_value());
+    }
+
+    let count = samples.len();
+    Ok((
+        Tensor::from_vec(inputs, (count, INPUT_PLANES, board_size, board_size), &Device::Cpu)?,
+        Tensor::from_vec(policies, (count, actions), &Device::Cpu)?,
+        Tensor::from_vec(values, count, &Device::Cpu)?,
+    ))
+}
+
+/// Policy and value losses of `network` on a batch.
+fn losses(network: &ResNet, inputs: &Tensor, policies: &Tensor, values: &Tensor) -> Result<(Tensor, Tensor)> {
+    let (logits, predicted) = network.forward(inputs)?;
+    let policy = (policies * ops::log_softmax(&logits, D::Minus1)?)?
+        .sum(D::Minus1)?
+        .mean_all()?
+        .neg()?;
+    let value = loss::mse(&predicted, values)?;
+    Ok((policy, value))
+}
+
+/// Trains `network` on `samples`, calling `on_epoch` after every pass so the caller can
+/// report progress and checkpoint. Returns the losses of every epoch.
+pub fn train(network: &ResNet, samples: &[Sample], config: &TrainConfig,
+             mut on_epoch: impl FnMut(&EpochLosses) -> Result<()>) -> Result<Vec<EpochLosses>> {
+    let board_size = network.get_config().board_size;
+    let params = ParamsAdamW { lr: config.learning_rate, weight_decay: config.weight_decay, ..Default::default() };
+    let mut optimizer = AdamW::new(network.get_varmap().all_vars(), params)?;
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    let mut order: Vec<&Sample> = samples.iter().collect();
+    let mut history = Vec::new();
+    for epoch in 1..=config.epochs {
+        order.shuffle(&mut rng);
+
+        let mut totals = EpochLosses { epoch, ..EpochLosses::default() };
+        let mut batches = 0;
+        for batch in order.chunks(config.batch_size.max(1)) {
+            let (inputs, policies, values) = batch_tensors(batch, board_size)?;
+            let (policy, value) = losses(network, &inputs, &policies, &values)?;
+            optimizer.backward_step(&(&policy + &value)?)?;
+
+            totals.policy += policy.to_scalar::<f32>()?;
+            totals.value += value.to_scalar::<f32>()?;
+            batches += 1;
+        }
+
+        if batches > 0 {
+            totals.policy /= batches as f32;
+            totals.value /= batches as f32;
+        }
+        on_epoch(&totals)?;
+        history.push(totals);
+    }
+
+    Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comun::Turn;
+    use crate::game_manager::GameManager;
+    use crate::ia::brain::REDVALUE;
+    use crate::ia::network::{encode_position, move_action};
+    use crate::ia::resnet::ResNetConfig;
+
+    #[test]
+    fn losses_go_down_on_a_fixed_position() {
+        let mut game_manager = GameManager::new();
+        let m = game_manager.get_legal_moves()[0].clone();
+        let sample = Sample {
+            planes: encode_position(&game_manager).iter().map(|&value| value as u8).collect(),
+            visits: vec![(move_action(&m, 8) as u16, 10)],
+            turn: Turn::Red,
+            outcome: REDVALUE,
+        };
+
+        let network = ResNet::new(ResNetConfig { board_size: 8, channels: 8, blocks: 1 }).unwrap();
+        let config = TrainConfig { epochs: 20, batch_size: 4, learning_rate: 1e-2, ..TrainConfig::default() };
+        let history = train(&network, &vec![sample; 8], &config, |_| Ok(())).unwrap();
+
+        assert_eq!(history.len(), 20);
+        let (first, last) = (history[0], history[19]);
+        assert!(last.policy < first.policy / 2.0, "{:?} -> {:?}", first, last);
+        assert!(last.value < first.value, "{:?} -> {:?}", first, last);
+    }
+}
@@ -0,0 +1,26 @@
+//! Draughts rules, notation and AI of Dark Queen Zer0, with no dependency on the GUI.
+
+mod bitboard;
+pub mod clock;
+pub mod comun;
+pub mod game_manager;
+pub mod notation;
+pub mod pdn;
+pub mod protocol {
+    pub mod dxp;
+    pub mod hub;
+    pub mod text;
+}
+pub mod rules;
+mod zobrist;
+pub mod ia {
+    pub mod arena;
+    pub mod brain;
+    pub mod mcts;
+    pub mod monte_carlo_impl;
+    pub mod network;
+    pub mod puct;
+    pub mod resnet;
+    pub mod selfplay;
+    pub mod train;
+}
@@ -2,21 +2,49 @@ use bevy::{prelude::*, window::{WindowMode, WindowResolution}};
 use bevy_mod_picking::DefaultPickingPlugins;
 
 mod board;
-mod game_manager;
-mod comun;
-mod ia {
-    pub mod monte_carlo_impl;
-    pub mod brain;
-}
 
 use board::setup_board;
-use crate::board::{create_pieces, HighlightedSquare, LegalMovesForPieceResource, PieceClicked, PieceIdByEntity, game_flow, button_system};
-use crate::game_manager::GameManager;
+use crate::board::{create_pieces, HighlightedSquare, LegalMovesForPieceResource, PieceClicked, PieceIdByEntity, game_flow, button_system, update_clock};
+use dark_queen_zer0::clock::{Clock, TimeControl};
+use dark_queen_zer0::game_manager::GameManager;
+use dark_queen_zer0::rules::Variant;
 
 #[derive(Resource, Default)]
 struct GameManagerResource(GameManager);
 
+/// DarkQueenZer0 [--clock <control>] [variant] [fen]; the dqz binary has the headless commands
+fn parse_args(mut args: Vec<String>) -> Result<GameManager, String> {
+    let control: Option<TimeControl> = match args.iter().position(|arg| arg == "--clock") {
+        Some(i) if i + 1 < args.len() => {
+            let value = args.drain(i..=i + 1).nth(1).unwrap();
+            Some(value.parse()?)
+        }
+        Some(_) => return Err("missing value for --clock".to_string()),
+        None => None,
+    };
+    let variant: Variant = match args.get(1) {
+        Some(arg) => arg.parse()?,
+        None => Variant::default(),
+    };
+    let mut game_manager = match args.get(2) {
+        Some(fen) => GameManager::from_fen(variant, fen).map_err(|e| e.to_string())?,
+        None => GameManager::with_variant(variant),
+    };
+    if let Some(control) = control {
+        game_manager.set_clock(Clock::new(control));
+    }
+    Ok(game_manager)
+}
+
 fn main() {
+    let game_manager = match parse_args(std::env::args().collect()) {
+        Ok(game_manager) => game_manager,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
     App::new()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window { 
@@ -31,10 +59,10 @@ fn main() {
         .init_resource::<PieceClicked>()
         .init_resource::<PieceIdByEntity>()
         .init_resource::<LegalMovesForPieceResource>()
-        .insert_resource(GameManagerResource(GameManager::new()))
+        .insert_resource(GameManagerResource(game_manager))
         .init_resource::<HighlightedSquare>()
         .add_systems(Startup, (setup, setup_board, create_pieces))
-        .add_systems(Update, (game_flow, button_system))
+        .add_systems(Update, (game_flow, button_system, update_clock))
         .add_plugins(DefaultPickingPlugins)
         .run();
 }
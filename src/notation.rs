@@ -0,0 +1,255 @@
+use std::fmt;
+
+use crate::comun::{Move, Square, Turn};
+
+/// Number of a playable square in the standard draughts numbering: square 1 is the
+/// leftmost playable square of the top row (Black's side) and numbers grow from left
+/// to right and then downwards, so White's back row holds the highest numbers.
+pub fn square_to_number(square: Square, board_size: usize) -> usize {
+    (board_size - 1 - square.row) * (board_size / 2) + square.col / 2 + 1
+}
+
+/// Inverse of `square_to_number`, or `None` when the number is not on the board.
+pub fn number_to_square(number: usize, board_size: usize) -> Option<Square> {
+    let per_row = board_size / 2;
+    if number == 0 || number > per_row * board_size {
+        return None;
+    }
+
+    let index = number - 1;
+    let row = board_size - 1 - index / per_row;
+    let col = (index % per_row) * 2 + row % 2;
+    Some(Square { row, col })
+}
+
+/// A move as players write it: the numbers of the squares it goes through, `11-15` for a
+/// step and `22x13x6` for a capture. Captures may give just the first and last squares
+/// (`22x6`) unless two different paths join them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MoveText {
+    pub squares: Vec<usize>,
+    pub capture: bool,
+}
+
+impl MoveText {
+    /// `m` with its whole path, or just its ends when `full_path` is false.
+    pub fn from_move(m: &Move, board_size: usize, full_path: bool) -> MoveText {
+        let squares: Vec<Square> = if full_path {
+            std::iter::once(m.from).chain(m.path.iter().copied()).collect()
+        } else {
+            vec![m.from, m.to]
+        };
+
+        MoveText {
+            squares: squares.into_iter().map(|square| square_to_number(square, board_size)).collect(),
+            capture: m.is_capture(),
+        }
+    }
+
+    /// The move written, for `GameManager::try_move`. Captured pieces are left for the game
+    /// to fill in, and a capture given by its ends has an empty path.
+    pub fn to_move(&self, turn: Turn, board_size: usize) -> Option<Move> {
+        let squares = self.squares.iter()
+            .map(|&number| number_to_square(number, board_size))
+            .collect::<Option<Vec<Square>>>()?;
+        let path = if squares.len() > 2 { squares[1..].to_vec() } else { Vec::new() };
+
+        Some(Move { turn, from: squares[0], to: squares[squares.len() - 1], path, eat: Vec::new(), promotion: false })
+    }
+
+    /// Whether `m` is the move written, on a board of `board_size`.
+    pub fn matches(&self, m: &Move, board_size: usize) -> bool {
+        let number = |square| square_to_number(square, board_size);
+        let (Some(&first), Some(&last)) = (self.squares.first(), self.squares.last()) else {
+            return false;
+        };
+
+        // a capture written with "-" is still accepted, as some programs do
+        (m.is_capture() || !self.capture)
+            && number(m.from) == first
+            && number(m.to) == last
+            && (self.squares.len() == 2 || m.path.iter().map(|&square| number(square)).eq(self.squares[1..].iter().copied()))
+    }
+}
+
+impl fmt::Display for MoveText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.capture { "x" } else { "-" };
+        let squares: Vec<String> = self.squares.iter().map(|number| number.to_string()).collect();
+        write!(f, "{}", squares.join(separator))
+    }
+}
+
+impl std::str::FromStr for MoveText {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let capture = s.contains(['x', 'X', ':']);
+        let squares = s.split(['-', 'x', 'X', ':'])
+            .map(|number| number.parse().map_err(|_| format!("bad move '{}'", s)))
+            .collect::<Result<Vec<usize>, String>>()?;
+
+        if squares.len() < 2 || (!capture && squares.len() > 2) {
+            return Err(format!("bad move '{}'", s));
+        }
+        Ok(MoveText { squares, capture })
+    }
+}
+
+/// Why a FEN string could not be turned into a position.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FenError {
+    /// The string does not have the `turn:pieces:pieces` shape.
+    Syntax(String),
+    /// A square number that is not on the board of the variant.
+    SquareOutOfRange(usize),
+    /// The same square is given more than once.
+    DuplicateSquare(usize),
+    /// A pawn standing on the row where it should have been crowned.
+    PawnOnLastRow(usize),
+    /// More pieces than a side has at the start of the game.
+    TooManyPieces(Turn),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::Syntax(reason) => write!(f, "invalid FEN: {}", reason),
+            FenError::SquareOutOfRange(number) => write!(f, "square {} is not on the board", number),
+            FenError::DuplicateSquare(number) => write!(f, "square {} is given twice", number),
+            FenError::PawnOnLastRow(number) => write!(f, "uncrowned pawn on the last row at square {}", number),
+            FenError::TooManyPieces(turn) => write!(f, "{:?} has more pieces than at the start", turn),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+/// A position as written in PDN FEN: `W:W21,22,K5:B1,2,3`. `W` is Red in the engine.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fen {
+    pub turn: Turn,
+    /// Square numbers of Red pieces, with whether each one is a queen.
+    pub red: Vec<(usize, bool)>,
+    pub black: Vec<(usize, bool)>,
+}
+
+fn parse_turn(text: &str) -> Result<Turn, FenError> {
+    match text.trim() {
+        "W" | "w" => Ok(Turn::Red),
+        "B" | "b" => Ok(Turn::Black),
+        other => Err(FenError::Syntax(format!("unknown side '{}'", other))),
+    }
+}
+
+fn parse_number(text: &str) -> Result<usize, FenError> {
+    text.trim().parse().map_err(|_| FenError::Syntax(format!("bad square '{}'", text.trim())))
+}
+
+/// Parses a comma separated list such as `K5,21-24,30` into (number, queen) pairs.
+fn parse_pieces(text: &str) -> Result<Vec<(usize, bool)>, FenError> {
+    let mut pieces = Vec::new();
+
+    for item in text.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (queen, item) = match item.strip_prefix(['K', 'k']) {
+            Some(rest) => (true, rest),
+            None => (false, item),
+        };
+
+        match item.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse_number(first)?, parse_number(last)?);
+                if first > last {
+                    return Err(FenError::Syntax(format!("empty range '{}'", item)));
+                }
+                pieces.extend((first..=last).map(|number| (number, queen)));
+            }
+            None => pieces.push((parse_number(item)?, queen)),
+        }
+    }
+
+    Ok(pieces)
+}
+
+impl std::str::FromStr for Fen {
+    type Err = FenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().trim_matches('"').trim_end_matches('.');
+        let mut fields = s.split(':');
+
+        let turn = parse_turn(fields.next().unwrap_or_default())?;
+        let mut fen = Fen { turn, ..Fen::default() };
+        let mut sides = 0;
+
+        for field in fields.map(str::trim).filter(|field| !field.is_empty()) {
+            let (side, pieces) = field.split_at(1);
+            match side {
+                "W" | "w" => fen.red.extend(parse_pieces(pieces)?),
+                "B" | "b" => fen.black.extend(parse_pieces(pieces)?),
+                // extra PDN fields such as move counters are ignored
+                _ => continue,
+            }
+            sides += 1;
+        }
+
+        if sides == 0 {
+            return Err(FenError::Syntax("no piece lists".to_string()));
+        }
+
+        Ok(fen)
+    }
+}
+
+fn write_pieces(f: &mut fmt::Formatter<'_>, side: &str, pieces: &[(usize, bool)]) -> fmt::Result {
+    let mut sorted = pieces.to_vec();
+    sorted.sort();
+
+    let items: Vec<String> = sorted.iter()
+        .map(|&(number, queen)| if queen { format!("K{}", number) } else { number.to_string() })
+        .collect();
+    write!(f, ":{}{}", side, items.join(","))
+}
+
+impl fmt::Display for Fen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", if self.turn == Turn::Red { "W" } else { "B" })?;
+        write_pieces(f, "W", &self.red)?;
+        write_pieces(f, "B", &self.black)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn square_numbers_round_trip() {
+        for board_size in [8, 10, 12] {
+            for number in 1..=board_size * board_size / 2 {
+                let square = number_to_square(number, board_size).unwrap();
+                assert_eq!((square.row + square.col) % 2, 0);
+                assert_eq!(square_to_number(square, board_size), number);
+            }
+            assert_eq!(number_to_square(0, board_size), None);
+            assert_eq!(number_to_square(board_size * board_size / 2 + 1, board_size), None);
+        }
+
+        assert_eq!(number_to_square(1, 8), Some(Square { row: 7, col: 1 }));
+        assert_eq!(number_to_square(29, 8), Some(Square { row: 0, col: 0 }));
+        assert_eq!(number_to_square(46, 10), Some(Square { row: 0, col: 0 }));
+    }
+
+    #[test]
+    fn move_text_round_trips() {
+        for text in ["11-15", "22x13x6", "22x6"] {
+            assert_eq!(text.parse::<MoveText>().unwrap().to_string(), text);
+        }
+
+        assert_eq!("22:6".parse::<MoveText>().unwrap().to_string(), "22x6");
+        assert!("11-15-19".parse::<MoveText>().is_err());
+        assert!("11".parse::<MoveText>().is_err());
+        assert!("a-15".parse::<MoveText>().is_err());
+    }
+}
@@ -0,0 +1,531 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::comun::{GameStatus, Turn};
+use crate::game_manager::GameManager;
+use crate::notation::{self, FenError, MoveText};
+use crate::rules::Variant;
+
+/// Longest movetext line written by `PdnGame`'s `Display`.
+const LINE_WIDTH: usize = 80;
+
+/// PDN `GameType` number of every variant.
+pub fn game_type(variant: Variant) -> u32 {
+    match variant {
+        Variant::International => 20,
+        Variant::English => 21,
+        Variant::Italian => 22,
+        Variant::Spanish => 24,
+        Variant::Russian => 25,
+        Variant::Brazilian => 26,
+        Variant::Canadian => 27,
+        Variant::Portuguese => 28,
+    }
+}
+
+fn variant_from_game_type(game_type: &str) -> Option<Variant> {
+    // the tag may carry extra board fields after the number, as in "20,W,10,10,N2,0"
+    let number: u32 = game_type.split(',').next()?.trim().parse().ok()?;
+    Variant::ALL.into_iter().find(|&variant| self::game_type(variant) == number)
+}
+
+/// Why a PDN text could not be read or replayed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PdnError {
+    Syntax(String),
+    UnknownGameType(String),
+    Fen(FenError),
+    /// A move of the main line that is not legal in its position, with its ply number.
+    IllegalMove { ply: usize, text: String },
+}
+
+impl fmt::Display for PdnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PdnError::Syntax(reason) => write!(f, "invalid PDN: {}", reason),
+            PdnError::UnknownGameType(game_type) => write!(f, "unknown GameType '{}'", game_type),
+            PdnError::Fen(error) => write!(f, "{}", error),
+            PdnError::IllegalMove { ply, text } => write!(f, "illegal move '{}' at ply {}", text, ply),
+        }
+    }
+}
+
+impl std::error::Error for PdnError {}
+
+impl From<FenError> for PdnError {
+    fn from(error: FenError) -> Self {
+        PdnError::Fen(error)
+    }
+}
+
+/// A move of a PDN record, with the comment written after it and the alternatives to it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PdnMove {
+    pub text: String,
+    pub comment: Option<String>,
+    pub variations: Vec<PdnLine>,
+}
+
+/// A sequence of moves, either the main line or a variation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PdnLine {
+    /// Comment written before the first move.
+    pub comment: Option<String>,
+    pub moves: Vec<PdnMove>,
+}
+
+/// Headers written by `GameManager::to_pdn`. Result, variant and start position come from the game.
+#[derive(Clone, Debug, Default)]
+pub struct PdnHeaders {
+    pub event: String,
+    pub site: String,
+    /// `YYYY.MM.DD`, today when empty.
+    pub date: String,
+    pub white: String,
+    pub black: String,
+    pub time_control: Option<String>,
+}
+
+/// One game of a PDN file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PdnGame {
+    /// Tag pairs in file order.
+    pub tags: Vec<(String, String)>,
+    pub moves: PdnLine,
+    pub result: String,
+}
+
+impl PdnGame {
+    pub fn get_tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Plays the main line from the start position of the record. Every move of the
+    /// returned game can be stepped through with `get_played_moves` and `undo_move`.
+    pub fn replay(&self) -> Result<GameManager, PdnError> {
+        let variant = match self.get_tag("GameType") {
+            Some(game_type) => variant_from_game_type(game_type)
+                .ok_or_else(|| PdnError::UnknownGameType(game_type.to_string()))?,
+            None => Variant::default(),
+        };
+
+        let mut game_manager = match self.get_tag("FEN") {
+            Some(fen) => GameManager::from_fen(variant, fen)?,
+            None => GameManager::with_variant(variant),
+        };
+
+        for (ply, pdn_move) in self.moves.moves.iter().enumerate() {
+            let m = game_manager.find_move(&pdn_move.text)
+                .ok_or_else(|| PdnError::IllegalMove { ply: ply + 1, text: pdn_move.text.clone() })?;
+            game_manager.do_move(&m);
+        }
+
+        Ok(game_manager)
+    }
+}
+
+fn result_text(status: &GameStatus) -> &'static str {
+    match status {
+        GameStatus::RedWins | GameStatus::LostOnTime(Turn::Black) => "1-0",
+        GameStatus::BlackWins | GameStatus::LostOnTime(Turn::Red) => "0-1",
+        GameStatus::Draw(_) => "1/2-1/2",
+        GameStatus::Playing => "*",
+    }
+}
+
+fn is_result(token: &str) -> bool {
+    matches!(token, "1-0" | "0-1" | "1/2-1/2" | "2-0" | "0-2" | "1-1" | "0-0" | "*")
+}
+
+/// Today's date as `YYYY.MM.DD`.
+fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs() / 86_400) as i64;
+
+    // civil date from days since 1970-01-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+impl GameManager {
+    /// The game so far as a PDN record, starting from the position the game started from.
+    pub fn to_pdn(&self, headers: &PdnHeaders) -> PdnGame {
+        let played = self.get_played_moves();
+        let mut replay = self.clone();
+        while replay.undo_move().is_some() {}
+
+        let result = result_text(&self.get_game_status()).to_string();
+        let date = if headers.date.is_empty() { today() } else { headers.date.clone() };
+        let mut tags = vec![
+            ("Event".to_string(), headers.event.clone()),
+            ("Site".to_string(), headers.site.clone()),
+            ("Date".to_string(), date),
+            ("White".to_string(), headers.white.clone()),
+            ("Black".to_string(), headers.black.clone()),
+            ("Result".to_string(), result.clone()),
+            ("GameType".to_string(), game_type(self.get_rules().variant).to_string()),
+        ];
+        // only the position counts, not who plays it
+        if replay.to_fen() != GameManager::with_variant(self.get_rules().variant).to_fen() {
+            tags.push(("FEN".to_string(), replay.to_fen()));
+        }
+        let time_control = headers.time_control.clone()
+            .or_else(|| self.get_clock().map(|clock| clock.get_control().to_string()));
+        if let Some(time_control) = time_control {
+            tags.push(("TimeControl".to_string(), time_control));
+        }
+
+        let mut moves = Vec::new();
+        for m in played.iter() {
+            moves.push(PdnMove { text: replay.get_move_text(m), ..PdnMove::default() });
+            replay.do_move(m);
+        }
+
+        PdnGame { tags, moves: PdnLine { comment: None, moves }, result }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Open,
+    Close,
+    Result(String),
+    Move(String),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, PdnError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '[' => {
+                chars.next();
+                let tag: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                let (key, value) = tag.trim().split_once(char::is_whitespace)
+                    .ok_or_else(|| PdnError::Syntax(format!("bad tag '[{}]'", tag)))?;
+                tokens.push(Token::Tag(key.to_string(), value.trim().trim_matches('"').to_string()));
+            }
+            '{' => {
+                chars.next();
+                let comment: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                tokens.push(Token::Comment(comment.trim().to_string()));
+            }
+            ';' => {
+                chars.next();
+                let comment: String = chars.by_ref().take_while(|&c| c != '\n').collect();
+                tokens.push(Token::Comment(comment.trim().to_string()));
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '$' => {
+                // numeric annotation glyphs are not kept
+                chars.next();
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && !"[]{}();".contains(c)) {
+                    word.push(c);
+                }
+
+                // "12." and "12..." are move numbers, which may be glued to the move
+                let word = match word.rfind('.') {
+                    Some(dot) if word[..dot].trim_end_matches('.').chars().all(|c| c.is_ascii_digit()) => word[dot + 1..].to_string(),
+                    _ => word,
+                };
+                let word = word.trim_end_matches(['!', '?']);
+
+                if word.is_empty() {
+                    continue;
+                } else if is_result(word) {
+                    tokens.push(Token::Result(word.to_string()));
+                } else if word.parse::<MoveText>().is_ok() {
+                    tokens.push(Token::Move(word.to_string()));
+                } else {
+                    return Err(PdnError::Syntax(format!("unexpected '{}'", word)));
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn add_comment(comment: &mut Option<String>, text: String) {
+    match comment {
+        Some(existing) => {
+            existing.push(' ');
+            existing.push_str(&text);
+        }
+        None => *comment = Some(text),
+    }
+}
+
+/// Reads moves until the end of the current line, which is `)` for a variation.
+fn parse_line(tokens: &mut std::iter::Peekable<std::vec::IntoIter<Token>>, nested: bool) -> Result<PdnLine, PdnError> {
+    let mut line = PdnLine::default();
+
+    while let Some(token) = tokens.peek() {
+        match token {
+            Token::Tag(_, _) | Token::Result(_) if !nested => break,
+            Token::Close if nested => {
+                tokens.next();
+                return Ok(line);
+            }
+            _ => {}
+        }
+
+        match tokens.next() {
+            Some(Token::Move(text)) => line.moves.push(PdnMove { text, ..PdnMove::default() }),
+            Some(Token::Comment(text)) => match line.moves.last_mut() {
+                Some(last) => add_comment(&mut last.comment, text),
+                None => add_comment(&mut line.comment, text),
+            },
+            Some(Token::Open) => {
+                let variation = parse_line(tokens, true)?;
+                line.moves.last_mut()
+                    .ok_or_else(|| PdnError::Syntax("variation before any move".to_string()))?
+                    .variations.push(variation);
+            }
+            Some(Token::Close) => return Err(PdnError::Syntax("unbalanced ')'".to_string())),
+            Some(Token::Result(_)) => {} // a result inside a variation says nothing about the game
+            Some(Token::Tag(key, _)) => return Err(PdnError::Syntax(format!("tag '{}' inside a variation", key))),
+            None => break,
+        }
+    }
+
+    if nested {
+        return Err(PdnError::Syntax("unclosed variation".to_string()));
+    }
+    Ok(line)
+}
+
+/// Every game of a PDN file.
+pub fn parse_pdn(text: &str) -> Result<Vec<PdnGame>, PdnError> {
+    let mut tokens = tokenize(text)?.into_iter().peekable();
+    let mut games = Vec::new();
+
+    while tokens.peek().is_some() {
+        let mut game = PdnGame { result: "*".to_string(), ..PdnGame::default() };
+
+        while let Some(Token::Tag(_, _)) = tokens.peek() {
+            if let Some(Token::Tag(key, value)) = tokens.next() {
+                game.tags.push((key, value));
+            }
+        }
+
+        game.moves = parse_line(&mut tokens, false)?;
+        if let Some(Token::Result(result)) = tokens.peek() {
+            game.result = result.clone();
+            tokens.next();
+        } else if let Some(result) = game.get_tag("Result") {
+            game.result = result.to_string();
+        }
+
+        games.push(game);
+    }
+
+    Ok(games)
+}
+
+impl FromStr for PdnGame {
+    type Err = PdnError;
+
+    /// Reads the first game of a PDN text.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_pdn(s)?.into_iter().next().ok_or_else(|| PdnError::Syntax("no game".to_string()))
+    }
+}
+
+/// Writes movetext items, wrapping lines at `LINE_WIDTH`.
+struct MoveTextWriter {
+    text: String,
+    column: usize,
+}
+
+impl MoveTextWriter {
+    fn push(&mut self, item: &str) {
+        if self.column > 0 && item != ")" && self.column + 1 + item.len() > LINE_WIDTH {
+            self.text.push('\n');
+            self.column = 0;
+        } else if self.column > 0 && item != ")" && !self.text.ends_with('(') {
+            self.text.push(' ');
+            self.column += 1;
+        }
+        self.text.push_str(item);
+        self.column += item.chars().count();
+    }
+
+    /// Writes `line`, whose first move is ply `ply` counted from the first mover of the game.
+    fn write_line(&mut self, line: &PdnLine, ply: usize) {
+        if let Some(comment) = &line.comment {
+            self.push(&format!("{{{}}}", comment));
+        }
+
+        let mut needs_number = true;
+        for (i, pdn_move) in line.moves.iter().enumerate() {
+            let ply = ply + i;
+            if ply.is_multiple_of(2) {
+                self.push(&format!("{}. {}", ply / 2 + 1, pdn_move.text));
+            } else if needs_number {
+                self.push(&format!("{}... {}", ply / 2 + 1, pdn_move.text));
+            } else {
+                self.push(&pdn_move.text);
+            }
+            needs_number = false;
+
+            if let Some(comment) = &pdn_move.comment {
+                self.push(&format!("{{{}}}", comment));
+                needs_number = true;
+            }
+            for variation in pdn_move.variations.iter() {
+                self.push("(");
+                self.write_line(variation, ply);
+                self.push(")");
+                needs_number = true;
+            }
+        }
+    }
+}
+
+impl fmt::Display for PdnGame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in self.tags.iter() {
+            writeln!(f, "[{} \"{}\"]", key, value)?;
+        }
+        writeln!(f)?;
+
+        // a record starting with the second player to move opens with "1..."
+        let first_turn = self.get_tag("GameType").and_then(variant_from_game_type).unwrap_or_default().rules().first_turn;
+        let turn = self.get_tag("FEN").and_then(|fen| fen.parse::<notation::Fen>().ok()).map_or(first_turn, |fen| fen.turn);
+        let first_ply = usize::from(turn != first_turn);
+
+        let mut writer = MoveTextWriter { text: String::new(), column: 0 };
+        writer.write_line(&self.moves, first_ply);
+        writer.push(&self.result);
+
+        writeln!(f, "{}", writer.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+    use super::*;
+    use crate::comun::{BandPlayer, GamePlayer};
+
+    const RECORD: &str = r#"[Event "Test"]
+[GameType "21"]
+[Result "*"]
+
+{Opening} 1. 11-15 {Old Fourteenth?} 23-19 (22-18 15x22 25x18) 2. 8-11! 22-17 $1
+3. 9-13 *
+
+[Event "Second"]
+[GameType "24"]
+[FEN "W:W26,K29:B22,7"]
+1. 29x2 1-0
+"#;
+
+    #[test]
+    fn reads_comments_and_variations() {
+        let games = parse_pdn(RECORD).unwrap();
+        assert_eq!(games.len(), 2);
+
+        let game = &games[0];
+        assert_eq!(game.get_tag("event"), Some("Test"));
+        assert_eq!(game.moves.comment.as_deref(), Some("Opening"));
+        let texts: Vec<&str> = game.moves.moves.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["11-15", "23-19", "8-11", "22-17", "9-13"]);
+        assert_eq!(game.moves.moves[0].comment.as_deref(), Some("Old Fourteenth?"));
+        assert_eq!(game.moves.moves[1].variations[0].moves.len(), 3);
+        assert_eq!(game.result, "*");
+
+        let replayed = game.replay().unwrap();
+        assert_eq!(replayed.get_played_moves().len(), 5);
+        assert_eq!(replayed.get_rules().variant, Variant::English);
+
+        let replayed = games[1].replay().unwrap();
+        assert_eq!(replayed.get_game_status(), GameStatus::RedWins);
+        assert_eq!(games[1].result, "1-0");
+
+        // what is written is read back the same
+        assert_eq!(parse_pdn(&games.iter().map(|game| game.to_string()).collect::<String>()).unwrap(), games);
+    }
+
+    #[test]
+    fn written_games_replay_to_the_same_game() {
+        for variant in Variant::ALL {
+            let mut rng = StdRng::seed_from_u64(game_type(variant) as u64);
+            let mut game_manager = GameManager::with_variant(variant);
+
+            while game_manager.get_game_status() == GameStatus::Playing && game_manager.get_played_moves().len() < 120 {
+                let m = game_manager.get_legal_moves().choose(&mut rng).cloned().unwrap();
+                game_manager.do_move(&m);
+            }
+
+            let headers = PdnHeaders { white: "Red".to_string(), black: "Black".to_string(), ..PdnHeaders::default() };
+            let text = game_manager.to_pdn(&headers).to_string();
+            let game: PdnGame = text.parse().unwrap();
+
+            assert_eq!(game.get_tag("GameType"), Some(game_type(variant).to_string().as_str()));
+            assert_eq!(game.result, result_text(&game_manager.get_game_status()));
+            assert_eq!(game.replay().unwrap().get_played_moves(), game_manager.get_played_moves(), "{}", variant);
+        }
+    }
+
+    #[test]
+    fn standard_start_has_no_fen_whoever_plays() {
+        let mut game_manager = GameManager::with_variant(Variant::English);
+        game_manager.set_band_player(BandPlayer { red: GamePlayer::Computer, black: GamePlayer::Computer });
+        let m = game_manager.get_legal_moves()[0].clone();
+        game_manager.do_move(&m);
+
+        let game = game_manager.to_pdn(&PdnHeaders::default());
+        assert_eq!(game.get_tag("FEN"), None);
+        assert_eq!(game.moves.moves.len(), 1);
+    }
+
+    #[test]
+    fn ambiguous_captures_are_written_with_their_path() {
+        let mut game_manager = GameManager::from_fen(Variant::Spanish, "W:W22:B9,10,17,18").unwrap();
+        let texts: Vec<String> = game_manager.get_legal_moves().clone().iter()
+            .map(|m| game_manager.get_move_text(m))
+            .collect();
+        assert_eq!(texts, ["22x15x6", "22x13x6"]);
+
+        let m = game_manager.find_move("22x13x6").unwrap();
+        assert_eq!(m.eat.len(), 2);
+        assert!(game_manager.find_move("22x6").is_none());
+
+        game_manager.do_move(&m);
+        let game = game_manager.to_pdn(&PdnHeaders::default());
+        assert_eq!(game.get_tag("FEN"), Some("W:W22:B9,10,17,18"));
+        assert_eq!(game.moves.moves[0].text, "22x13x6");
+    }
+}
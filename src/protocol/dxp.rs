@@ -0,0 +1,475 @@
+//! DamExchange Protocol (DXP) to play engine matches over TCP.
+//!
+//! Messages are ASCII strings ended by a NUL byte, with a one letter type followed by fixed
+//! width fields. Squares use the standard numbering and colors are `W` (white, Red in the
+//! engine) and `Z` (black). DXP does not name the variant, so both sides must agree on it.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use crate::comun::{DrawReason, GameStatus, Move, Turn, BLACK_PAWN, BLACK_QUEEN, WHITE_PAWN, WHITE_QUEEN};
+use crate::game_manager::GameManager;
+use crate::ia::brain::SearchLimits;
+use crate::notation;
+
+const VERSION: u8 = 1;
+const NAME_WIDTH: usize = 32;
+
+/// GAMEACC acceptance codes.
+pub const ACCEPTED: u8 = 0;
+pub const REFUSED: u8 = 9;
+/// BACKACC code for a take back that is not done.
+pub const BACK_NOT_SUPPORTED: u8 = 1;
+
+/// GAMEEND reasons, as seen by the side sending the message.
+pub const END_UNKNOWN: u8 = 0;
+pub const END_I_LOSE: u8 = 1;
+pub const END_DRAW: u8 = 2;
+pub const END_I_WIN: u8 = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DxpMessage {
+    Chat(String),
+    GameReq {
+        version: u8,
+        name: String,
+        /// Color played by the side accepting the game.
+        follower_color: Turn,
+        minutes: u32,
+        moves: u32,
+        /// Side to move and one of `ewzWZ` per square, when not starting from the initial position.
+        position: Option<(Turn, String)>,
+    },
+    GameAcc { name: String, code: u8 },
+    Move { seconds: u32, from: usize, to: usize, captures: Vec<usize> },
+    GameEnd { reason: u8, stop: u8 },
+    BackReq { move_number: u32, turn: Turn },
+    BackAcc { code: u8 },
+}
+
+fn color(turn: Turn) -> char {
+    match turn {
+        Turn::Red => 'W',
+        Turn::Black => 'Z',
+    }
+}
+
+fn parse_color(c: &str) -> Result<Turn, String> {
+    match c {
+        "W" => Ok(Turn::Red),
+        "Z" => Ok(Turn::Black),
+        _ => Err(format!("bad color '{}'", c)),
+    }
+}
+
+impl fmt::Display for DxpMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DxpMessage::Chat(text) => write!(f, "C{}", text),
+            DxpMessage::GameReq { version, name, follower_color, minutes, moves, position } => {
+                write!(f, "R{:02}{:<width$.width$}{}{:03}{:03}", version, name, color(*follower_color), minutes, moves, width = NAME_WIDTH)?;
+                match position {
+                    Some((turn, squares)) => write!(f, "B{}{}", color(*turn), squares),
+                    None => write!(f, "A"),
+                }
+            }
+            DxpMessage::GameAcc { name, code } => write!(f, "A{:<width$.width$}{}", name, code, width = NAME_WIDTH),
+            DxpMessage::Move { seconds, from, to, captures } => {
+                write!(f, "M{:04}{:02}{:02}{:02}", seconds, from, to, captures.len())?;
+                captures.iter().try_for_each(|square| write!(f, "{:02}", square))
+            }
+            DxpMessage::GameEnd { reason, stop } => write!(f, "E{}{}", reason, stop),
+            DxpMessage::BackReq { move_number, turn } => write!(f, "B{:03}{}", move_number, color(*turn)),
+            DxpMessage::BackAcc { code } => write!(f, "K{}", code),
+        }
+    }
+}
+
+/// Reads fixed width fields off the front of a message.
+struct Fields<'a>(&'a str);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, width: usize) -> Result<&'a str, String> {
+        if self.0.len() < width || !self.0.is_char_boundary(width) {
+            return Err("message too short".to_string());
+        }
+        let (field, rest) = self.0.split_at(width);
+        self.0 = rest;
+        Ok(field)
+    }
+
+    fn number<T: FromStr>(&mut self, width: usize) -> Result<T, String> {
+        let field = self.take(width)?;
+        field.trim().parse().map_err(|_| format!("bad number '{}'", field))
+    }
+}
+
+impl FromStr for DxpMessage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = Fields(s);
+
+        let message = match fields.take(1)? {
+            "C" => DxpMessage::Chat(fields.0.to_string()),
+            "R" => DxpMessage::GameReq {
+                version: fields.number(2)?,
+                name: fields.take(NAME_WIDTH)?.trim_end().to_string(),
+                follower_color: parse_color(fields.take(1)?)?,
+                minutes: fields.number(3)?,
+                moves: fields.number(3)?,
+                position: match fields.take(1)? {
+                    "A" => None,
+                    "B" => Some((parse_color(fields.take(1)?)?, fields.0.to_string())),
+                    other => return Err(format!("bad start position flag '{}'", other)),
+                },
+            },
+            "A" => DxpMessage::GameAcc {
+                name: fields.take(NAME_WIDTH)?.trim_end().to_string(),
+                code: fields.number(1)?,
+            },
+            "M" => {
+                let seconds = fields.number(4)?;
+                let from = fields.number(2)?;
+                let to = fields.number(2)?;
+                let count: usize = fields.number(2)?;
+                let captures = (0..count).map(|_| fields.number(2)).collect::<Result<_, _>>()?;
+                DxpMessage::Move { seconds, from, to, captures }
+            }
+            "E" => DxpMessage::GameEnd { reason: fields.number(1)?, stop: fields.number(1)? },
+            "B" => DxpMessage::BackReq { move_number: fields.number(3)?, turn: parse_color(fields.take(1)?)? },
+            "K" => DxpMessage::BackAcc { code: fields.number(1)? },
+            other => return Err(format!("unknown message type '{}'", other)),
+        };
+
+        Ok(message)
+    }
+}
+
+/// The position as the `ewzWZ` square string of a GAMEREQ.
+fn position_squares(game_manager: &GameManager) -> String {
+    let size = game_manager.get_board_size();
+    (1..=size * size / 2)
+        .map(|number| {
+            let square = notation::number_to_square(number, size).unwrap();
+            match game_manager.get_board()[square.row][square.col] {
+                WHITE_PAWN => 'w',
+                BLACK_PAWN => 'z',
+                WHITE_QUEEN => 'W',
+                BLACK_QUEEN => 'Z',
+                _ => 'e',
+            }
+        })
+        .collect()
+}
+
+/// FEN of a GAMEREQ position, to set it up with `GameManager::from_fen`.
+fn position_fen(turn: Turn, squares: &str) -> String {
+    let mut red = Vec::new();
+    let mut black = Vec::new();
+
+    for (i, c) in squares.chars().enumerate() {
+        let number = i + 1;
+        match c {
+            'w' => red.push(number.to_string()),
+            'W' => red.push(format!("K{}", number)),
+            'z' => black.push(number.to_string()),
+            'Z' => black.push(format!("K{}", number)),
+            _ => {}
+        }
+    }
+
+    let side = if turn == Turn::Red { "W" } else { "B" };
+    format!("{}:W{}:B{}", side, red.join(","), black.join(","))
+}
+
+/// Receives every message exchanged, as `> text` when sent and `< text` when received.
+pub type DxpLog = Box<dyn FnMut(&str) + Send>;
+
+/// A connection to the other engine.
+pub struct DxpConnection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    log: Option<DxpLog>,
+}
+
+impl DxpConnection {
+    pub fn new(stream: TcpStream) -> io::Result<DxpConnection> {
+        Ok(DxpConnection { reader: BufReader::new(stream.try_clone()?), writer: stream, log: None })
+    }
+
+    /// Shows the messages exchanged through `log`.
+    pub fn set_log(&mut self, log: impl FnMut(&str) + Send + 'static) {
+        self.log = Some(Box::new(log));
+    }
+
+    pub fn send(&mut self, message: &DxpMessage) -> io::Result<()> {
+        if let Some(log) = &mut self.log {
+            log(&format!("> {}", message));
+        }
+        self.writer.write_all(message.to_string().as_bytes())?;
+        self.writer.write_all(&[0])?;
+        self.writer.flush()
+    }
+
+    pub fn receive(&mut self) -> io::Result<DxpMessage> {
+        let mut bytes = Vec::new();
+        if self.reader.read_until(0, &mut bytes)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+        }
+        bytes.pop_if(|byte| *byte == 0);
+
+        let text = String::from_utf8_lossy(&bytes);
+        if let Some(log) = &mut self.log {
+            log(&format!("< {}", text));
+        }
+        text.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Settings of the games this engine offers or accepts.
+#[derive(Clone, Debug)]
+pub struct DxpConfig {
+    pub name: String,
+    /// Color this engine plays when it starts the game.
+    pub color: Turn,
+    /// Thinking time of each side for `moves` moves.
+    pub minutes: u32,
+    pub moves: u32,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Offers a game from the position of `game_manager` and plays it once accepted.
+pub fn initiate(connection: &mut DxpConnection, mut game_manager: GameManager, config: &DxpConfig) -> io::Result<GameStatus> {
+    let start = GameManager::with_variant(game_manager.get_rules().variant);
+    let position = (game_manager.get_board() != start.get_board() || game_manager.get_turn() != start.get_turn())
+        .then(|| (game_manager.get_turn(), position_squares(&game_manager)));
+
+    connection.send(&DxpMessage::GameReq {
+        version: VERSION,
+        name: config.name.clone(),
+        follower_color: if config.color == Turn::Red { Turn::Black } else { Turn::Red },
+        minutes: config.minutes,
+        moves: config.moves,
+        position,
+    })?;
+
+    loop {
+        match connection.receive()? {
+            DxpMessage::GameAcc { code: ACCEPTED, .. } => break,
+            DxpMessage::GameAcc { code, .. } => return Err(invalid(format!("game refused with code {}", code))),
+            DxpMessage::Chat(_) => {}
+            other => return Err(invalid(format!("expected GAMEACC, got {:?}", other))),
+        }
+    }
+
+    play(connection, &mut game_manager, config.color, config)
+}
+
+/// Waits for a game request and plays it. The variant is the one of `game_manager`.
+pub fn follow(connection: &mut DxpConnection, game_manager: GameManager, name: &str) -> io::Result<GameStatus> {
+    loop {
+        match connection.receive()? {
+            DxpMessage::GameReq { follower_color, minutes, moves, position, .. } => {
+                let variant = game_manager.get_rules().variant;
+                let game = match &position {
+                    Some((turn, squares)) => GameManager::from_fen(variant, &position_fen(*turn, squares)),
+                    None => Ok(GameManager::with_variant(variant)),
+                };
+
+                let Ok(mut game) = game else {
+                    connection.send(&DxpMessage::GameAcc { name: name.to_string(), code: REFUSED })?;
+                    continue;
+                };
+                game.share_brain(&game_manager);
+                connection.send(&DxpMessage::GameAcc { name: name.to_string(), code: ACCEPTED })?;
+
+                let config = DxpConfig { name: name.to_string(), color: follower_color, minutes, moves };
+                return play(connection, &mut game, follower_color, &config);
+            }
+            DxpMessage::Chat(_) => {}
+            other => return Err(invalid(format!("expected GAMEREQ, got {:?}", other))),
+        }
+    }
+}
+
+/// GAMEEND reason of the finished game, as seen by `color`.
+fn end_reason(status: &GameStatus, color: Turn) -> u8 {
+    match (status.get_winner(), status) {
+        (Some(winner), _) if winner == color => END_I_WIN,
+        (Some(_), _) => END_I_LOSE,
+        (None, GameStatus::Draw(_)) => END_DRAW,
+        (None, _) => END_UNKNOWN,
+    }
+}
+
+/// Result of a GAMEEND sent by the engine playing `their_color`.
+fn status_from_end(reason: u8, their_color: Turn) -> GameStatus {
+    match (reason, their_color) {
+        (END_I_WIN, Turn::Red) | (END_I_LOSE, Turn::Black) => GameStatus::RedWins,
+        (END_I_WIN, Turn::Black) | (END_I_LOSE, Turn::Red) => GameStatus::BlackWins,
+        (END_DRAW, _) => GameStatus::Draw(DrawReason::Agreement),
+        _ => GameStatus::Playing,
+    }
+}
+
+/// The legal move a MOVE message describes. Captures are told apart by the captured squares.
+fn find_dxp_move(game_manager: &mut GameManager, from: usize, to: usize, captures: &[usize]) -> Option<Move> {
+    let size = game_manager.get_board_size();
+    let number = |square| notation::square_to_number(square, size);
+    let mut captures = captures.to_vec();
+    captures.sort();
+
+    game_manager.get_legal_moves().iter()
+        .find(|m| {
+            let mut eaten: Vec<usize> = m.eat.iter().map(|(square, _)| number(*square)).collect();
+            eaten.sort();
+            number(m.from) == from && number(m.to) == to && eaten == captures
+        })
+        .cloned()
+}
+
+fn play(connection: &mut DxpConnection, game_manager: &mut GameManager, color: Turn, config: &DxpConfig) -> io::Result<GameStatus> {
+    // the whole thinking time spread over the moves of the time control
+    let move_time = Duration::from_secs(u64::from(config.minutes) * 60) / config.moves.max(1);
+
+    loop {
+        let status = game_manager.get_game_status();
+        if status != GameStatus::Playing {
+            connection.send(&DxpMessage::GameEnd { reason: end_reason(&status, color), stop: 1 })?;
+            // the other side confirms with its own GAMEEND
+            while !matches!(connection.receive()?, DxpMessage::GameEnd { .. }) {}
+            return Ok(status);
+        }
+
+        if game_manager.get_turn() == color {
+            let started = Instant::now();
+            let limits = SearchLimits { time: Some(move_time), ..SearchLimits::default() };
+            let m = game_manager.computer_search(&limits, &AtomicBool::new(false), &mut |_| {})
+                .ok_or_else(|| invalid("no move to play".to_string()))?;
+            game_manager.try_move(&m).map_err(|e| invalid(e.to_string()))?;
+
+            let size = game_manager.get_board_size();
+            connection.send(&DxpMessage::Move {
+                seconds: started.elapsed().as_secs() as u32,
+                from: notation::square_to_number(m.from, size),
+                to: notation::square_to_number(m.to, size),
+                captures: m.eat.iter().map(|(square, _)| notation::square_to_number(*square, size)).collect(),
+            })?;
+            continue;
+        }
+
+        match connection.receive()? {
+            DxpMessage::Move { from, to, captures, .. } => {
+                let Some(m) = find_dxp_move(game_manager, from, to, &captures) else {
+                    connection.send(&DxpMessage::GameEnd { reason: END_UNKNOWN, stop: 1 })?;
+                    return Err(invalid(format!("illegal move {}-{}", from, to)));
+                };
+                game_manager.try_move(&m).map_err(|e| invalid(e.to_string()))?;
+            }
+            DxpMessage::GameEnd { reason, .. } => {
+                // the game ended for the other side only, such as a resignation or a draw offer taken
+                let status = status_from_end(reason, color.opposite());
+                connection.send(&DxpMessage::GameEnd { reason: end_reason(&status, color), stop: 1 })?;
+                return Ok(status);
+            }
+            DxpMessage::BackReq { move_number, turn } => {
+                // move numbers count full moves from the start of the game
+                let first = if game_manager.get_played_moves().is_empty() { game_manager.get_turn() } else { game_manager.get_played_moves()[0].turn };
+                let ply = (move_number.max(1) as usize - 1) * 2 + usize::from(turn != first);
+                let accepted = ply <= game_manager.get_played_moves().len();
+                if accepted {
+                    while game_manager.get_played_moves().len() > ply {
+                        game_manager.undo_move();
+                    }
+                }
+                connection.send(&DxpMessage::BackAcc { code: if accepted { ACCEPTED } else { BACK_NOT_SUPPORTED } })?;
+            }
+            DxpMessage::Chat(_) | DxpMessage::BackAcc { .. } => {}
+            other => return Err(invalid(format!("unexpected {:?}", other))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+    use crate::ia::brain::Brain;
+    use crate::rules::Variant;
+    use super::*;
+
+    /// Always plays the first legal move, so loopback games are quick and repeatable.
+    struct FirstMove;
+
+    impl Brain for FirstMove {
+        fn get_best_move(&mut self, mut game_manager: GameManager) -> Option<Move> {
+            game_manager.get_legal_moves().first().cloned()
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            DxpMessage::Chat("hello".to_string()),
+            DxpMessage::GameReq { version: 1, name: "Dark Queen Zer0".to_string(), follower_color: Turn::Black, minutes: 10, moves: 75, position: None },
+            DxpMessage::GameReq { version: 1, name: "x".to_string(), follower_color: Turn::Red, minutes: 1, moves: 50, position: Some((Turn::Black, "wez".repeat(16) + "ee")) },
+            DxpMessage::GameAcc { name: "Scan".to_string(), code: ACCEPTED },
+            DxpMessage::Move { seconds: 3, from: 32, to: 23, captures: vec![28] },
+            DxpMessage::GameEnd { reason: END_I_WIN, stop: 0 },
+            DxpMessage::BackReq { move_number: 12, turn: Turn::Red },
+            DxpMessage::BackAcc { code: BACK_NOT_SUPPORTED },
+        ];
+
+        for message in messages {
+            assert_eq!(message.to_string().parse::<DxpMessage>(), Ok(message.clone()));
+        }
+
+        assert_eq!(DxpMessage::Move { seconds: 12, from: 32, to: 23, captures: vec![28] }.to_string(), "M0012322301 28".replace(' ', ""));
+        assert_eq!(DxpMessage::GameAcc { name: "Scan".to_string(), code: 0 }.to_string().len(), 1 + NAME_WIDTH + 1);
+        assert!("Q".parse::<DxpMessage>().is_err());
+        assert!("M00".parse::<DxpMessage>().is_err());
+    }
+
+    #[test]
+    fn position_squares_round_trip() {
+        let game_manager = GameManager::from_fen(Variant::International, "B:W28,K45:B19,K3").unwrap();
+        let squares = position_squares(&game_manager);
+        assert_eq!(squares.len(), 50);
+
+        let fen = position_fen(Turn::Black, &squares);
+        assert_eq!(GameManager::from_fen(Variant::International, &fen).unwrap(), game_manager);
+    }
+
+    #[test]
+    fn loopback_game() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let follower = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut connection = DxpConnection::new(stream).unwrap();
+            let mut game_manager = GameManager::with_variant(Variant::International);
+            game_manager.set_brain(FirstMove);
+            follow(&mut connection, game_manager, "follower").unwrap()
+        });
+
+        let mut connection = DxpConnection::new(TcpStream::connect(address).unwrap()).unwrap();
+        let mut game_manager = GameManager::with_variant(Variant::International);
+        game_manager.set_brain(FirstMove);
+        let config = DxpConfig { name: "initiator".to_string(), color: Turn::Red, minutes: 1, moves: 50 };
+
+        let status = initiate(&mut connection, game_manager, &config).unwrap();
+        let follower_status = follower.join().unwrap();
+
+        assert_ne!(status, GameStatus::Playing);
+        assert_eq!(std::mem::discriminant(&status), std::mem::discriminant(&follower_status));
+    }
+}
@@ -0,0 +1,412 @@
+//! Hub protocol, spoken by Scan and the GUIs built for it.
+//!
+//! Every line is a command followed by `name=value` pairs, values with spaces being quoted.
+//! Positions are the side to move (`W` or `B`) followed by one of `wbWBe` per square in the
+//! standard numbering, and captures are written `from`x`to` followed by `x` and every
+//! captured square, such as `28x19x23`.
+//!
+//! ```text
+//! GUI -> engine                                    engine -> GUI
+//! hub                                              id ..., param ..., wait
+//! init                                             ready
+//! set-param name=variant value=<name>
+//! new-game
+//! pos [pos=<position>] [moves="<move> ..."]
+//! level [move-time=<s>] [time=<s>] [inc=<s>] [moves=<n>] [nodes=<n>] [infinite]
+//! go think|ponder|analyze                          info depth=<d> score=<s> nodes=<n> time=<s> pv="<moves>"
+//!                                                  done [move=<move>]
+//! ponder-hit, stop, ping (pong), quit
+//! ```
+
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::clock::TimeLeft;
+use crate::comun::{Move, Turn, BLACK_PAWN, BLACK_QUEEN, WHITE_PAWN, WHITE_QUEEN};
+use crate::game_manager::GameManager;
+use crate::ia::brain::{SearchInfo, SearchLimits};
+use crate::notation;
+use crate::protocol::text::{send, Output};
+use crate::rules::Variant;
+
+/// Splits a line into the command and its `name=value` pairs. A name without a value,
+/// like `infinite`, gets an empty one.
+pub fn parse_line(line: &str) -> (String, Vec<(String, String)>) {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+
+    for c in line.trim().chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    let mut words = words.into_iter();
+    let command = words.next().unwrap_or_default();
+    let pairs = words
+        .map(|word| match word.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => (word, String::new()),
+        })
+        .collect();
+
+    (command, pairs)
+}
+
+fn value<'a>(pairs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    pairs.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+/// `m` in Hub notation.
+pub fn move_text(m: &Move, board_size: usize) -> String {
+    let number = |square| notation::square_to_number(square, board_size);
+
+    if m.is_capture() {
+        let mut captured: Vec<usize> = m.eat.iter().map(|(square, _)| number(*square)).collect();
+        captured.sort();
+        let captured: Vec<String> = captured.iter().map(|square| square.to_string()).collect();
+        format!("{}x{}x{}", number(m.from), number(m.to), captured.join("x"))
+    } else {
+        format!("{}-{}", number(m.from), number(m.to))
+    }
+}
+
+/// The legal move written as `text` in Hub notation.
+pub fn find_move(game_manager: &mut GameManager, text: &str) -> Option<Move> {
+    let size = game_manager.get_board_size();
+    game_manager.get_legal_moves().iter().find(|m| move_text(m, size) == text).cloned()
+}
+
+/// The position in Hub notation.
+pub fn position_text(game_manager: &GameManager) -> String {
+    let size = game_manager.get_board_size();
+    let side = if game_manager.get_turn() == Turn::Red { 'W' } else { 'B' };
+
+    std::iter::once(side)
+        .chain((1..=size * size / 2).map(|number| {
+            let square = notation::number_to_square(number, size).unwrap();
+            match game_manager.get_board()[square.row][square.col] {
+                WHITE_PAWN => 'w',
+                BLACK_PAWN => 'b',
+                WHITE_QUEEN => 'W',
+                BLACK_QUEEN => 'B',
+                _ => 'e',
+            }
+        }))
+        .collect()
+}
+
+/// Sets up a Hub position on the board of `variant`.
+pub fn from_position_text(variant: Variant, text: &str) -> Result<GameManager, String> {
+    let mut chars = text.chars();
+    let side = match chars.next() {
+        Some('W') => "W",
+        Some('B') => "B",
+        _ => return Err(format!("bad position '{}'", text)),
+    };
+
+    let mut red = Vec::new();
+    let mut black = Vec::new();
+    for (i, c) in chars.enumerate() {
+        let number = i + 1;
+        match c {
+            'w' => red.push(number.to_string()),
+            'W' => red.push(format!("K{}", number)),
+            'b' => black.push(number.to_string()),
+            'B' => black.push(format!("K{}", number)),
+            'e' => {}
+            _ => return Err(format!("bad position '{}'", text)),
+        }
+    }
+
+    GameManager::from_fen(variant, &format!("{}:W{}:B{}", side, red.join(","), black.join(",")))
+        .map_err(|e| e.to_string())
+}
+
+/// Search budget set by `level`.
+#[derive(Clone, Debug, Default)]
+struct Level {
+    move_time: Option<Duration>,
+    time: Option<Duration>,
+    increment: Duration,
+    moves: Option<u32>,
+    nodes: Option<u64>,
+    infinite: bool,
+}
+
+impl Level {
+    fn limits(&self) -> SearchLimits {
+        let clock = self.time.map(|remaining| TimeLeft { remaining, increment: self.increment, moves_to_go: self.moves });
+        SearchLimits { time: self.move_time, nodes: self.nodes, clock, infinite: self.infinite }
+    }
+}
+
+fn seconds(text: &str) -> Result<Duration, String> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|seconds| *seconds >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("bad time '{}'", text))
+}
+
+pub struct HubEngine {
+    game_manager: GameManager,
+    output: Output,
+    level: Level,
+    stop: Arc<AtomicBool>,
+    search: Option<JoinHandle<()>>,
+}
+
+impl HubEngine {
+    pub fn new(game_manager: GameManager, output: Output) -> HubEngine {
+        HubEngine { game_manager, output, level: Level::default(), stop: Arc::new(AtomicBool::new(false)), search: None }
+    }
+
+    /// Answers commands until `quit` or the end of `input`.
+    pub fn run(&mut self, input: impl BufRead) -> io::Result<()> {
+        for line in input.lines() {
+            if !self.handle(&line?) {
+                break;
+            }
+        }
+        self.stop_search();
+        Ok(())
+    }
+
+    /// Handles one command, returning false on `quit`.
+    pub fn handle(&mut self, line: &str) -> bool {
+        let (command, pairs) = parse_line(line);
+
+        let result = match command.as_str() {
+            "" => Ok(()),
+            "hub" => {
+                let variants: Vec<String> = Variant::ALL.iter().map(|variant| variant.to_string()).collect();
+                send(&self.output, &format!("id name=\"Dark Queen Zer0\" version={} author=\"DarkQueenZer0 developers\"", env!("CARGO_PKG_VERSION")));
+                send(&self.output, &format!("param name=variant value={} type=enum values=\"{}\"",
+                                            self.game_manager.get_rules().variant, variants.join(" ")));
+                send(&self.output, "wait");
+                Ok(())
+            }
+            "init" => {
+                send(&self.output, "ready");
+                Ok(())
+            }
+            "ping" => {
+                send(&self.output, "pong");
+                Ok(())
+            }
+            "set-param" => self.set_param(&pairs),
+            "new-game" => {
+                self.stop_search();
+                self.reset(GameManager::with_variant(self.game_manager.get_rules().variant));
+                Ok(())
+            }
+            "pos" => {
+                self.stop_search();
+                self.set_position(&pairs)
+            }
+            "level" => self.set_level(&pairs),
+            "go" => {
+                self.stop_search();
+                let pondering = pairs.iter().any(|(name, _)| name == "ponder" || name == "analyze");
+                self.go(pondering);
+                Ok(())
+            }
+            "ponder-hit" => {
+                // the expected move was played: think for the normal time from now on
+                let limits = self.level.limits();
+                if let Some(time) = limits.time.or(limits.clock.map(|clock| clock.get_budget())) {
+                    let stop = self.stop.clone();
+                    thread::spawn(move || {
+                        thread::sleep(time);
+                        stop.store(true, Ordering::Relaxed);
+                    });
+                }
+                Ok(())
+            }
+            "stop" => {
+                self.stop_search();
+                Ok(())
+            }
+            "quit" => return false,
+            _ => Err(format!("unknown command '{}'", command)),
+        };
+
+        if let Err(error) = result {
+            send(&self.output, &format!("error message=\"{}\"", error));
+        }
+        true
+    }
+
+    /// Keeps the players and the AI when the game changes.
+    fn reset(&mut self, mut game_manager: GameManager) {
+        game_manager.set_band_player(self.game_manager.get_band_player().clone());
+        game_manager.share_brain(&self.game_manager);
+        self.game_manager = game_manager;
+    }
+
+    fn set_param(&mut self, pairs: &[(String, String)]) -> Result<(), String> {
+        match (value(pairs, "name"), value(pairs, "value")) {
+            (Some("variant"), Some(name)) => {
+                // Scan calls international draughts "normal"
+                let variant = if name == "normal" { Variant::International } else { name.parse()? };
+                self.stop_search();
+                self.reset(GameManager::with_variant(variant));
+                Ok(())
+            }
+            (Some(name), _) => Err(format!("unknown param '{}'", name)),
+            (None, _) => Err("set-param needs a name".to_string()),
+        }
+    }
+
+    fn set_position(&mut self, pairs: &[(String, String)]) -> Result<(), String> {
+        let variant = self.game_manager.get_rules().variant;
+        let mut game_manager = match value(pairs, "pos") {
+            Some(position) => from_position_text(variant, position)?,
+            None => GameManager::with_variant(variant),
+        };
+
+        for text in value(pairs, "moves").unwrap_or_default().split_whitespace() {
+            let m = find_move(&mut game_manager, text).ok_or_else(|| format!("illegal move {}", text))?;
+            game_manager.try_move(&m).map_err(|e| e.to_string())?;
+        }
+
+        self.reset(game_manager);
+        Ok(())
+    }
+
+    fn set_level(&mut self, pairs: &[(String, String)]) -> Result<(), String> {
+        let mut level = Level::default();
+
+        for (name, text) in pairs {
+            match name.as_str() {
+                "move-time" => level.move_time = Some(seconds(text)?),
+                "time" => level.time = Some(seconds(text)?),
+                "inc" => level.increment = seconds(text)?,
+                "moves" => level.moves = Some(text.parse().map_err(|_| format!("bad moves '{}'", text))?),
+                "nodes" => level.nodes = Some(text.parse().map_err(|_| format!("bad nodes '{}'", text))?),
+                "infinite" => level.infinite = true,
+                // the Monte Carlo search has no depth to limit
+                "depth" => {}
+                _ => return Err(format!("unknown level '{}'", name)),
+            }
+        }
+
+        self.level = level;
+        Ok(())
+    }
+
+    fn go(&mut self, pondering: bool) {
+        let mut game_manager = self.game_manager.clone();
+        let output = self.output.clone();
+        let limits = if pondering { SearchLimits { infinite: true, ..SearchLimits::default() } } else { self.level.limits() };
+        // a fresh flag, so a late ponder-hit timer cannot stop the next search
+        self.stop = Arc::new(AtomicBool::new(false));
+        let stop = self.stop.clone();
+
+        self.search = Some(thread::spawn(move || {
+            let root = game_manager.clone();
+            let size = root.get_board_size();
+            let best = game_manager.computer_search(&limits, &stop, &mut |info: SearchInfo| {
+                let pv: Vec<String> = info.pv.iter().map(|m| move_text(m, size)).collect();
+                send(&output, &format!("info depth={} score={:.0} nodes={} time={:.3} pv=\"{}\"",
+                                       info.depth, info.score * 100.0, info.nodes, info.time.as_secs_f64(), pv.join(" ")));
+            });
+
+            match best {
+                Some(m) => send(&output, &format!("done move={}", move_text(&m, size))),
+                None => send(&output, "done"),
+            }
+        }));
+    }
+
+    fn stop_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.stop.store(true, Ordering::Relaxed);
+            let _ = search.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Mutex;
+    use super::*;
+
+    fn run(commands: &str) -> Vec<String> {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let output: Output = buffer.clone();
+
+        HubEngine::new(GameManager::with_variant(Variant::International), output)
+            .run(Cursor::new(commands.to_string()))
+            .unwrap();
+
+        let text = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn parses_quoted_values() {
+        let (command, pairs) = parse_line("pos pos=Wbbe moves=\"32-28 19-23\"  infinite");
+        assert_eq!(command, "pos");
+        assert_eq!(pairs, [
+            ("pos".to_string(), "Wbbe".to_string()),
+            ("moves".to_string(), "32-28 19-23".to_string()),
+            ("infinite".to_string(), String::new()),
+        ]);
+    }
+
+    #[test]
+    fn positions_and_moves_round_trip() {
+        let mut game_manager = GameManager::from_fen(Variant::International, "W:W28,K46:B23,13,K3").unwrap();
+        let text = position_text(&game_manager);
+        assert_eq!(text.len(), 51);
+        assert_eq!(from_position_text(Variant::International, &text).unwrap(), game_manager);
+
+        let moves = game_manager.get_legal_moves().clone();
+        let texts: Vec<String> = moves.iter().map(|m| move_text(m, 10)).collect();
+        assert_eq!(texts, ["28x8x13x23"]);
+        assert_eq!(find_move(&mut game_manager, "28x8x13x23"), Some(moves[0].clone()));
+    }
+
+    #[test]
+    fn handshake_and_search() {
+        let lines = run("hub\ninit\nping\npos moves=\"32-28 19-23\"\nlevel nodes=3000\ngo think\nquit\n");
+
+        assert!(lines[0].starts_with("id name=\"Dark Queen Zer0\""));
+        assert!(lines[1].starts_with("param name=variant value=international"));
+        assert_eq!(lines[2..5], ["wait", "ready", "pong"]);
+
+        let best = lines.iter().find_map(|line| line.strip_prefix("done move=")).unwrap();
+        let mut game_manager = GameManager::with_variant(Variant::International);
+        for text in ["32-28", "19-23", best] {
+            let m = find_move(&mut game_manager, text).unwrap();
+            assert_eq!(game_manager.try_move(&m), Ok(()));
+        }
+    }
+
+    #[test]
+    fn reports_errors() {
+        let lines = run("pos moves=\"32-23\"\nlevel depth=3 speed=9\nset-param name=variant value=klingon\nfly\n");
+        assert_eq!(lines, [
+            "error message=\"illegal move 32-23\"",
+            "error message=\"unknown level 'speed'\"",
+            "error message=\"unknown variant 'klingon'\"",
+            "error message=\"unknown command 'fly'\"",
+        ]);
+    }
+}
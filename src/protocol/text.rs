@@ -0,0 +1,251 @@
+//! Line protocol to drive the engine from another program, modelled on UCI.
+//!
+//! ```text
+//! GUI -> engine                                   engine -> GUI
+//! dqi                                             id name ..., id author ..., dqiok
+//! isready                                         readyok
+//! newgame
+//! position [variant <name>] startpos|fen <fen> [moves <move>...]
+//! go [movetime <ms>] [nodes <n>] [infinite]       info depth <d> score <s> nodes <n> time <ms> pv <moves>
+//!                                                 bestmove <move> | bestmove none
+//! stop
+//! quit
+//! ```
+//!
+//! Moves use numeric notation (`11-15`, `22x13x6`) and scores go from -100 to 100 for the
+//! side to move. A search runs in the background, so `stop` and `isready` are answered while
+//! it thinks.
+
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::comun::Move;
+use crate::game_manager::GameManager;
+use crate::ia::brain::{SearchInfo, SearchLimits};
+use crate::rules::Variant;
+
+pub type Output = Arc<Mutex<dyn Write + Send>>;
+
+pub struct TextEngine {
+    game_manager: GameManager,
+    output: Output,
+    stop: Arc<AtomicBool>,
+    search: Option<JoinHandle<()>>,
+}
+
+pub(crate) fn send(output: &Output, line: &str) {
+    let mut output = output.lock().unwrap();
+    // a GUI that went away is noticed when reading its next command
+    let _ = writeln!(output, "{}", line);
+    let _ = output.flush();
+}
+
+/// `moves` played one after the other from the current position, in notation.
+fn line_text(game_manager: &GameManager, moves: &[Move]) -> String {
+    let mut game_manager = game_manager.clone();
+    let mut texts = Vec::new();
+
+    for m in moves {
+        texts.push(game_manager.get_move_text(m));
+        game_manager.do_move(m);
+    }
+    texts.join(" ")
+}
+
+fn info_line(game_manager: &GameManager, info: &SearchInfo) -> String {
+    format!("info depth {} score {:.0} nodes {} time {} pv {}",
+            info.depth, info.score * 100.0, info.nodes, info.time.as_millis(), line_text(game_manager, &info.pv))
+}
+
+/// Parses the limits of a `go` command.
+fn parse_limits(words: &[&str]) -> Result<SearchLimits, String> {
+    let mut limits = SearchLimits::default();
+    let mut words = words.iter();
+
+    while let Some(&word) = words.next() {
+        let mut number = || words.next()
+            .and_then(|value| value.parse::<u64>().ok())
+            .ok_or_else(|| format!("go {} needs a number", word));
+
+        match word {
+            "movetime" => limits.time = Some(Duration::from_millis(number()?)),
+            "nodes" => limits.nodes = Some(number()?),
+            "infinite" => limits.infinite = true,
+            _ => return Err(format!("unknown go option '{}'", word)),
+        }
+    }
+
+    Ok(limits)
+}
+
+impl TextEngine {
+    pub fn new(game_manager: GameManager, output: Output) -> TextEngine {
+        TextEngine { game_manager, output, stop: Arc::new(AtomicBool::new(false)), search: None }
+    }
+
+    /// Answers commands until `quit` or the end of `input`.
+    pub fn run(&mut self, input: impl BufRead) -> io::Result<()> {
+        for line in input.lines() {
+            if !self.handle(&line?) {
+                break;
+            }
+        }
+        self.stop_search();
+        Ok(())
+    }
+
+    /// Handles one command, returning false on `quit`.
+    pub fn handle(&mut self, line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, arguments)) = words.split_first() else {
+            return true;
+        };
+
+        let result = match command {
+            "dqi" => {
+                send(&self.output, &format!("id name Dark Queen Zer0 {}", env!("CARGO_PKG_VERSION")));
+                send(&self.output, "id author DarkQueenZer0 developers");
+                send(&self.output, "dqiok");
+                Ok(())
+            }
+            "isready" => {
+                send(&self.output, "readyok");
+                Ok(())
+            }
+            "newgame" => {
+                self.stop_search();
+                let mut game_manager = GameManager::with_variant(self.game_manager.get_rules().variant);
+                game_manager.set_band_player(self.game_manager.get_band_player().clone());
+                game_manager.share_brain(&self.game_manager);
+                self.game_manager = game_manager;
+                Ok(())
+            }
+            "position" => {
+                self.stop_search();
+                self.set_position(arguments)
+            }
+            "go" => {
+                self.stop_search();
+                parse_limits(arguments).map(|limits| self.go(limits))
+            }
+            "stop" => {
+                self.stop_search();
+                Ok(())
+            }
+            "quit" => return false,
+            _ => Err(format!("unknown command '{}'", command)),
+        };
+
+        if let Err(error) = result {
+            send(&self.output, &format!("info string {}", error));
+        }
+        true
+    }
+
+    fn set_position(&mut self, words: &[&str]) -> Result<(), String> {
+        let mut words = words;
+        let mut variant = self.game_manager.get_rules().variant;
+
+        if let ["variant", name, rest @ ..] = words {
+            variant = name.parse::<Variant>()?;
+            words = rest;
+        }
+
+        let moves_at = words.iter().position(|&word| word == "moves").unwrap_or(words.len());
+        let (setup, moves) = words.split_at(moves_at);
+
+        let mut game_manager = match setup {
+            ["startpos"] => GameManager::with_variant(variant),
+            ["fen", fen @ ..] if !fen.is_empty() => GameManager::from_fen(variant, &fen.join(" ")).map_err(|e| e.to_string())?,
+            _ => return Err("position needs startpos or fen <fen>".to_string()),
+        };
+        game_manager.set_band_player(self.game_manager.get_band_player().clone());
+        game_manager.share_brain(&self.game_manager);
+
+        for text in moves.iter().skip(1) {
+            let m = game_manager.find_move(text).ok_or_else(|| format!("illegal move '{}'", text))?;
+            game_manager.try_move(&m).map_err(|e| format!("{}: {}", text, e))?;
+        }
+
+        self.game_manager = game_manager;
+        Ok(())
+    }
+
+    fn go(&mut self, limits: SearchLimits) {
+        let mut game_manager = self.game_manager.clone();
+        let output = self.output.clone();
+        let stop = self.stop.clone();
+        stop.store(false, Ordering::Relaxed);
+
+        self.search = Some(thread::spawn(move || {
+            let root = game_manager.clone();
+            let best = game_manager.computer_search(&limits, &stop, &mut |info| send(&output, &info_line(&root, &info)));
+
+            match best {
+                Some(m) => send(&output, &format!("bestmove {}", line_text(&root, &[m]))),
+                None => send(&output, "bestmove none"),
+            }
+        }));
+    }
+
+    /// Stops the running search, if any, once it has sent its `bestmove`.
+    fn stop_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.stop.store(true, Ordering::Relaxed);
+            let _ = search.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    fn run(commands: &str) -> Vec<String> {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let output: Output = buffer.clone();
+
+        TextEngine::new(GameManager::new(), output).run(Cursor::new(commands.to_string())).unwrap();
+
+        let text = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn handshake_and_search() {
+        let lines = run("dqi\nisready\nposition startpos moves 22-18 11-15\ngo nodes 3000\nisready\nquit\n");
+
+        assert_eq!(lines[..2], ["id name Dark Queen Zer0 0.1.0", "id author DarkQueenZer0 developers"]);
+        assert_eq!(lines[2..4], ["dqiok", "readyok"]);
+        assert!(lines.iter().any(|line| line.starts_with("info depth")));
+
+        let best = lines.iter().find_map(|line| line.strip_prefix("bestmove ")).unwrap();
+        let mut game_manager = GameManager::new();
+        for text in ["22-18", "11-15", best] {
+            let m = game_manager.find_move(text).unwrap();
+            assert_eq!(game_manager.try_move(&m), Ok(()));
+        }
+    }
+
+    #[test]
+    fn reports_bad_commands() {
+        let lines = run("position startpos moves 22-18 22-18\nposition variant klingon startpos\ngo depth 3\nfly\n");
+
+        assert_eq!(lines, [
+            "info string illegal move '22-18'",
+            "info string unknown variant 'klingon'",
+            "info string unknown go option 'depth'",
+            "info string unknown command 'fly'",
+        ]);
+    }
+
+    #[test]
+    fn game_over_has_no_best_move() {
+        let lines = run("position variant english fen B:W18:B\ngo\nquit\n");
+        assert_eq!(lines, ["bestmove none"]);
+    }
+}
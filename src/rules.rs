@@ -0,0 +1,192 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::comun::Turn;
+
+/// Draughts families the engine knows how to play.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Variant {
+    #[default]
+    Spanish,
+    Italian,
+    Portuguese,
+    Russian,
+    Brazilian,
+    English,
+    International,
+    Canadian,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 8] = [
+        Variant::Spanish,
+        Variant::Italian,
+        Variant::Portuguese,
+        Variant::Russian,
+        Variant::Brazilian,
+        Variant::English,
+        Variant::International,
+        Variant::Canadian,
+    ];
+
+    pub fn rules(&self) -> RuleSet {
+        RuleSet::new(*self)
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Variant::Spanish => "spanish",
+            Variant::Italian => "italian",
+            Variant::Portuguese => "portuguese",
+            Variant::Russian => "russian",
+            Variant::Brazilian => "brazilian",
+            Variant::English => "english",
+            Variant::International => "international",
+            Variant::Canadian => "canadian",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Variant::ALL.iter()
+            .find(|variant| variant.to_string().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| format!("unknown variant '{}'", s))
+    }
+}
+
+/// Which capture sequences are legal when several are available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CapturePriority {
+    /// Any capture sequence may be chosen, as long as it is completed.
+    Free,
+    /// The sequence capturing the most pieces.
+    Quantity,
+    /// Spanish "ley de cantidad y calidad": most pieces, then most queens,
+    /// then the capture made with a queen.
+    QuantityAndQuality,
+    /// Italian rules: most pieces, then the capture made with a queen, then most queens,
+    /// then the sequence that meets a queen first.
+    Italian,
+}
+
+/// What happens when a pawn reaches the last row in the middle of a capture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromotionDuringCapture {
+    /// The capture ends there and the pawn is crowned.
+    Stop,
+    /// The pawn keeps capturing as a pawn and is only crowned if it ends on the last row.
+    ContinueAsPawn,
+    /// The pawn is crowned at once and keeps capturing as a queen.
+    ContinueAsQueen,
+}
+
+/// The rules of a variant, read by `GameManager` for setup and move generation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RuleSet {
+    pub variant: Variant,
+    pub board_size: usize,
+    /// Rows filled with pawns by each side at the start.
+    pub pawn_rows: usize,
+    pub first_turn: Turn,
+    pub pawns_capture_backward: bool,
+    /// Whether pawns are allowed to capture queens.
+    pub pawns_capture_queens: bool,
+    /// Long range queens that move and capture along whole diagonals.
+    pub flying_queens: bool,
+    pub capture_priority: CapturePriority,
+    pub promotion_during_capture: PromotionDuringCapture,
+    /// Plies without a capture or a pawn move after which the game is drawn.
+    pub no_progress_limit: Option<u16>,
+    /// Plies the stronger side has to beat a lone queen with `queen_endgame_queens` queens.
+    pub queen_endgame_limit: Option<u16>,
+    pub queen_endgame_queens: usize,
+    /// Times the same position has to appear to draw the game.
+    pub repetition_limit: usize,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self::new(Variant::default())
+    }
+}
+
+impl RuleSet {
+    pub fn new(variant: Variant) -> RuleSet {
+        let spanish = RuleSet {
+            variant,
+            board_size: 8,
+            pawn_rows: 3,
+            first_turn: Turn::Red,
+            pawns_capture_backward: false,
+            pawns_capture_queens: true,
+            flying_queens: true,
+            capture_priority: CapturePriority::QuantityAndQuality,
+            promotion_during_capture: PromotionDuringCapture::Stop,
+            no_progress_limit: Some(50),
+            queen_endgame_limit: Some(24),
+            queen_endgame_queens: 3,
+            repetition_limit: 3,
+        };
+
+        match variant {
+            Variant::Spanish => spanish,
+            // Portuguese draughts is played with the Spanish rules
+            Variant::Portuguese => spanish,
+            Variant::Italian => RuleSet {
+                pawns_capture_queens: false,
+                flying_queens: false,
+                capture_priority: CapturePriority::Italian,
+                ..spanish
+            },
+            Variant::Russian => RuleSet {
+                pawns_capture_backward: true,
+                capture_priority: CapturePriority::Free,
+                promotion_during_capture: PromotionDuringCapture::ContinueAsQueen,
+                // 15 moves each
+                no_progress_limit: Some(30),
+                queen_endgame_limit: Some(30),
+                ..spanish
+            },
+            Variant::Brazilian => RuleSet {
+                pawns_capture_backward: true,
+                capture_priority: CapturePriority::Quantity,
+                promotion_during_capture: PromotionDuringCapture::ContinueAsPawn,
+                queen_endgame_limit: Some(32),
+                ..spanish
+            },
+            Variant::English => RuleSet {
+                first_turn: Turn::Black,
+                flying_queens: false,
+                capture_priority: CapturePriority::Free,
+                // the 40 move rule, and kings have to win against a lone king
+                no_progress_limit: Some(80),
+                queen_endgame_limit: None,
+                ..spanish
+            },
+            Variant::International => RuleSet {
+                board_size: 10,
+                pawn_rows: 4,
+                pawns_capture_backward: true,
+                capture_priority: CapturePriority::Quantity,
+                promotion_during_capture: PromotionDuringCapture::ContinueAsPawn,
+                // 25 moves each of queens only, 16 moves each against a lone queen
+                no_progress_limit: Some(50),
+                queen_endgame_limit: Some(32),
+                ..spanish
+            },
+            Variant::Canadian => RuleSet {
+                variant,
+                board_size: 12,
+                pawn_rows: 5,
+                ..RuleSet::new(Variant::International)
+            },
+        }
+    }
+}
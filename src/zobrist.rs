@@ -0,0 +1,55 @@
+use crate::comun::{BLACK_PAWN, BLACK_QUEEN, Square, WHITE_PAWN, WHITE_QUEEN};
+
+/// Largest board side the key table covers (Canadian draughts).
+pub const MAX_BOARD_SIZE: usize = 12;
+
+const PIECE_KINDS: usize = 4;
+const SEED: u64 = 0x4451_5A30_5A4F_4252;
+
+/// Random keys for every (square, piece) pair, generated at compile time with SplitMix64
+/// so hashes are the same on every run and every machine.
+const PIECE_KEYS: [[u64; PIECE_KINDS]; MAX_BOARD_SIZE * MAX_BOARD_SIZE] = generate_piece_keys();
+const BLACK_TO_MOVE_KEY: u64 = splitmix64(SEED ^ 0xB1AC_0000_0000_0001);
+
+const fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const fn generate_piece_keys() -> [[u64; PIECE_KINDS]; MAX_BOARD_SIZE * MAX_BOARD_SIZE] {
+    let mut keys = [[0; PIECE_KINDS]; MAX_BOARD_SIZE * MAX_BOARD_SIZE];
+    let mut state = SEED;
+    let mut square = 0;
+
+    while square < MAX_BOARD_SIZE * MAX_BOARD_SIZE {
+        let mut kind = 0;
+        while kind < PIECE_KINDS {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            keys[square][kind] = splitmix64(state);
+            kind += 1;
+        }
+        square += 1;
+    }
+
+    keys
+}
+
+/// Key of `piece` standing on `square`, or 0 for an empty square.
+pub fn piece_key(square: Square, piece: i8) -> u64 {
+    let kind = match piece {
+        WHITE_PAWN => 0,
+        WHITE_QUEEN => 1,
+        BLACK_PAWN => 2,
+        BLACK_QUEEN => 3,
+        _ => return 0,
+    };
+
+    PIECE_KEYS[square.row * MAX_BOARD_SIZE + square.col][kind]
+}
+
+/// Key toggled every time the side to move changes.
+pub fn side_key() -> u64 {
+    BLACK_TO_MOVE_KEY
+}